@@ -0,0 +1,127 @@
+use cpal::StreamInstant;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Proportional gain of the drift control loop, per second of FIFO error.
+const KP: f64 = 0.05;
+/// Integral gain of the drift control loop.
+const KI: f64 = 0.002;
+/// Largest clock ratio correction applied, 0.5%.
+const MAX_CORRECTION: f64 = 0.005;
+
+/// Buffers the input of one device and reads it back on the clock of the
+/// primary device.
+///
+/// Every device's FIFO is kept at `target` frames. The primary device is read
+/// at exactly the rate it delivers, the others are resampled with a ratio
+/// that a PI loop adjusts to hold their FIFO level steady. Once the loop has
+/// settled, the integral term is the clock drift between the two devices.
+pub struct DeviceFifo {
+    channels: usize,
+    sample_rate: f64,
+    target: usize,
+    fifo: VecDeque<f32>,
+    /// Capture time of the newest frame in the FIFO.
+    newest: Option<StreamInstant>,
+    position: f64,
+    integral: f64,
+    ratio: f64,
+}
+
+impl DeviceFifo {
+    pub fn new(channels: usize, sample_rate: u32, target: usize) -> Self {
+        DeviceFifo {
+            channels,
+            sample_rate: sample_rate as f64,
+            target,
+            fifo: VecDeque::new(),
+            newest: None,
+            position: 0.,
+            integral: 0.,
+            ratio: 1.,
+        }
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn frames(&self) -> usize {
+        self.fifo.len() / self.channels
+    }
+
+    /// Appends a callback buffer captured at `capture`.
+    pub fn push(&mut self, capture: StreamInstant, samples: &[f32]) {
+        self.fifo.extend(samples);
+        let frames = samples.len() / self.channels;
+        self.newest = capture.add(Duration::from_secs_f64(frames as f64 / self.sample_rate));
+    }
+
+    pub fn newest(&self) -> Option<StreamInstant> {
+        self.newest
+    }
+
+    /// Aligns the FIFO so that it holds exactly `target` frames up to the one
+    /// captured at `end`. Frames captured after `end` are kept, so the next
+    /// buffer pushed still follows on from the last.
+    pub fn prime(&mut self, end: StreamInstant) {
+        let late = self
+            .newest
+            .and_then(|newest| newest.duration_since(&end))
+            .map_or(0, |late| {
+                (late.as_secs_f64() * self.sample_rate).round() as usize
+            });
+        let excess = self.frames().saturating_sub(self.target + late);
+        self.fifo.drain(..excess * self.channels);
+        self.position = 0.;
+    }
+
+    /// Reads `frames` frames at the device's own rate, appending them to `out`.
+    /// Returns how many frames were missing and padded with silence.
+    pub fn pull_exact(&mut self, frames: usize, out: &mut Vec<f32>) -> usize {
+        let available = frames.min(self.frames());
+        out.extend(self.fifo.drain(..available * self.channels));
+        out.resize(out.len() + (frames - available) * self.channels, 0.);
+        frames - available
+    }
+
+    /// Reads `frames` frames resampled onto the primary clock, appending them
+    /// to `out`. Returns how many frames were missing and padded with silence.
+    pub fn pull_resampled(&mut self, frames: usize, out: &mut Vec<f32>) -> usize {
+        let error = (self.frames() as f64 - self.position - self.target as f64) / self.sample_rate;
+        let integral = self.integral + error * frames as f64 / self.sample_rate;
+        let correction = KP * error + KI * integral;
+        // Stop integrating while saturated, so a long stall can't wind it up.
+        if correction.abs() < MAX_CORRECTION {
+            self.integral = integral;
+        }
+        self.ratio = 1. + correction.clamp(-MAX_CORRECTION, MAX_CORRECTION);
+
+        let mut missing = 0;
+        for _ in 0..frames {
+            let index = self.position as usize;
+            if (index + 1) * self.channels >= self.fifo.len() {
+                missing += 1;
+                out.resize(out.len() + self.channels, 0.);
+                continue;
+            }
+            let frac = (self.position - index as f64) as f32;
+            for channel in 0..self.channels {
+                let a = self.fifo[index * self.channels + channel];
+                let b = self.fifo[(index + 1) * self.channels + channel];
+                out.push(a + (b - a) * frac);
+            }
+            self.position += self.ratio;
+        }
+
+        let consumed = (self.position as usize).min(self.frames());
+        self.fifo.drain(..consumed * self.channels);
+        self.position -= consumed as f64;
+        missing
+    }
+
+    /// Clock drift of this device against the primary one, in parts per million.
+    pub fn drift_ppm(&self) -> f64 {
+        KI * self.integral * 1e6
+    }
+}
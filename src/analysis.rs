@@ -0,0 +1,382 @@
+use std::ops::Range;
+
+use crate::levels::Level;
+
+/// Samples at or above this magnitude count as clipped.
+pub const CLIP_LEVEL: f32 = 0.999;
+
+/// Length of the windows the noise floor is measured over.
+const WINDOW_SECS: f64 = 0.05;
+/// Window levels are counted in steps of this many dB, from `FLOOR_DB` to 0.
+const STEP_DB: f64 = 0.5;
+const FLOOR_DB: f64 = -160.;
+/// The noise floor is the level the quietest tenth of the windows stay under.
+const NOISE_PERCENTILE: f64 = 0.1;
+
+pub fn to_db(value: f64) -> f64 {
+    20. * value.log10()
+}
+
+/// Minimum, maximum and RMS of `samples`.
+pub fn level(samples: impl IntoIterator<Item = f32>) -> Level {
+    let mut level = Level::default();
+    let mut square_sum = 0.;
+    let mut count = 0;
+    for sample in samples {
+        level.min = level.min.min(sample);
+        level.max = level.max.max(sample);
+        square_sum += sample * sample;
+        count += 1;
+    }
+    level.rms = (square_sum / count.max(1) as f32).sqrt();
+    level
+}
+
+/// The level of each channel of interleaved `samples`.
+pub fn channel_levels(samples: &[f32], channels: usize) -> Vec<Level> {
+    (0..channels)
+        .map(|channel| level(samples.iter().skip(channel).step_by(channels).copied()))
+        .collect()
+}
+
+/// Signal statistics of one channel, gathered a sample at a time so that
+/// recordings and files of any length can be analysed in constant memory.
+#[derive(Clone)]
+pub struct ChannelStats {
+    sample_rate: u32,
+    samples: u64,
+    sum: f64,
+    square_sum: f64,
+    peak: f32,
+    clipped: u64,
+    crossings: u64,
+    /// Whether the last sample that wasn't zero was positive.
+    positive: Option<bool>,
+    /// Every sample scaled to a 32-bit integer, ORed together.
+    bits: i64,
+    /// Set once a sample has finer steps than 32-bit audio.
+    fractional: bool,
+    window: usize,
+    window_samples: usize,
+    window_square_sum: f64,
+    /// How many windows had each RMS level, in `STEP_DB` steps.
+    histogram: Vec<u64>,
+}
+
+impl ChannelStats {
+    pub fn new(sample_rate: u32) -> Self {
+        ChannelStats {
+            sample_rate,
+            samples: 0,
+            sum: 0.,
+            square_sum: 0.,
+            peak: 0.,
+            clipped: 0,
+            crossings: 0,
+            positive: None,
+            bits: 0,
+            fractional: false,
+            window: ((sample_rate as f64 * WINDOW_SECS) as usize).max(1),
+            window_samples: 0,
+            window_square_sum: 0.,
+            histogram: vec![0; (-FLOOR_DB / STEP_DB) as usize + 1],
+        }
+    }
+
+    pub fn add(&mut self, sample: f32) {
+        let value = sample as f64;
+        self.samples += 1;
+        self.sum += value;
+        self.square_sum += value * value;
+        self.peak = self.peak.max(sample.abs());
+        if sample.abs() >= CLIP_LEVEL {
+            self.clipped += 1;
+        }
+        if sample != 0. {
+            let positive = sample > 0.;
+            if self.positive.is_some_and(|last| last != positive) {
+                self.crossings += 1;
+            }
+            self.positive = Some(positive);
+        }
+
+        let scaled = value * 2147483648.;
+        if scaled.fract() != 0. {
+            self.fractional = true;
+        } else {
+            self.bits |= scaled as i64;
+        }
+
+        self.window_square_sum += value * value;
+        self.window_samples += 1;
+        if self.window_samples == self.window {
+            let rms = (self.window_square_sum / self.window as f64).sqrt();
+            // Digital silence says nothing about the noise of the signal.
+            if rms > 0. {
+                let last = self.histogram.len() - 1;
+                let step = ((to_db(rms) - FLOOR_DB) / STEP_DB).max(0.) as usize;
+                self.histogram[step.min(last)] += 1;
+            }
+            self.window_samples = 0;
+            self.window_square_sum = 0.;
+        }
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    pub fn peak_db(&self) -> f64 {
+        to_db(self.peak as f64)
+    }
+
+    pub fn rms(&self) -> f64 {
+        (self.square_sum / self.samples.max(1) as f64).sqrt()
+    }
+
+    pub fn rms_db(&self) -> f64 {
+        to_db(self.rms())
+    }
+
+    /// The mean of the samples, as a fraction of full scale.
+    pub fn dc_offset(&self) -> f64 {
+        self.sum / self.samples.max(1) as f64
+    }
+
+    /// Peak over RMS; 1.41 for a sine wave.
+    pub fn crest_factor(&self) -> f64 {
+        self.peak as f64 / self.rms()
+    }
+
+    pub fn clipped(&self) -> u64 {
+        self.clipped
+    }
+
+    /// How many times a second the signal changes sign.
+    pub fn zero_crossing_rate(&self) -> f64 {
+        self.crossings as f64 * self.sample_rate as f64 / self.samples.max(1) as f64
+    }
+
+    /// The bits of resolution the samples actually use: 16 for 24-bit audio
+    /// whose lowest 8 bits are always zero, for instance. Float samples finer
+    /// than 32-bit steps count as 32.
+    pub fn bits_used(&self) -> u32 {
+        if self.fractional {
+            32
+        } else if self.bits == 0 {
+            0
+        } else {
+            32u32.saturating_sub(self.bits.trailing_zeros())
+        }
+    }
+
+    /// The RMS level, in dBFS, that the quietest tenth of 50 ms windows stay
+    /// under. Windows of digital silence are left out; a channel of nothing
+    /// else has a floor of -inf.
+    pub fn noise_floor_db(&self) -> f64 {
+        let windows: u64 = self.histogram.iter().sum();
+        if windows == 0 {
+            // Shorter than a window, or silent throughout.
+            return if self.rms() > 0. {
+                self.rms_db()
+            } else {
+                f64::NEG_INFINITY
+            };
+        }
+        let wanted = ((windows as f64 * NOISE_PERCENTILE).ceil() as u64).max(1);
+        let mut counted = 0;
+        for (step, &count) in self.histogram.iter().enumerate() {
+            counted += count;
+            if counted >= wanted {
+                return FLOOR_DB + (step + 1) as f64 * STEP_DB;
+            }
+        }
+        0.
+    }
+
+    /// Peak level over the noise floor, in dB.
+    pub fn dynamic_range_db(&self) -> f64 {
+        self.peak_db() - self.noise_floor_db()
+    }
+}
+
+/// A second order IIR filter, in transposed direct form II.
+#[derive(Clone)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// The K-weighting of ITU-R BS.1770 at `sample_rate`: a high shelf for the
+/// head, then a high pass. Coefficients as derived by libebur128, so they hold
+/// at any rate, not just 48 kHz.
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = sample_rate as f64;
+
+    let (f0, gain, q) = (1681.974450955533, 3.999843853973347, 0.7071752369554196);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let vh = 10f64.powf(gain / 20.);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        z: [0.; 2],
+    };
+
+    let (f0, q) = (38.13547087602444, 0.5003270373238773);
+    let k = (std::f64::consts::PI * f0 / rate).tan();
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1., -2., 1.],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        z: [0.; 2],
+    };
+    [shelf, high_pass]
+}
+
+/// Integrated loudness in LUFS, after ITU-R BS.1770-4 and EBU R 128, gathered
+/// a frame at a time: the K-weighted power of 400 ms blocks overlapping by
+/// 75%, gated at -70 LUFS and then 10 LU below the mean of what is left.
+pub struct Loudness {
+    filters: Vec<[Biquad; 2]>,
+    weights: Vec<f64>,
+    /// Frames in 100 ms, a quarter of a block.
+    step: usize,
+    frames: usize,
+    step_power: f64,
+    /// Power of the last three complete steps, the most recent last.
+    previous: [f64; 3],
+    steps: usize,
+    /// Mean power of every block.
+    blocks: Vec<f64>,
+}
+
+impl Loudness {
+    pub fn new(channels: usize, sample_rate: u32) -> Self {
+        // Surround channels count 1.5 dB more and the LFE not at all, in the
+        // usual L R C (LFE) Ls Rs order.
+        let weights = match channels {
+            5 => vec![1., 1., 1., 1.41, 1.41],
+            6 => vec![1., 1., 1., 0., 1.41, 1.41],
+            _ => vec![1.; channels],
+        };
+        Loudness {
+            filters: vec![k_weighting(sample_rate); channels],
+            weights,
+            step: ((sample_rate as f64 * 0.1).round() as usize).max(1),
+            frames: 0,
+            step_power: 0.,
+            previous: [0.; 3],
+            steps: 0,
+            blocks: Vec::new(),
+        }
+    }
+
+    pub fn add_frame(&mut self, frame: &[f32]) {
+        for ((&sample, filters), weight) in frame.iter().zip(&mut self.filters).zip(&self.weights) {
+            let [shelf, high_pass] = filters;
+            let weighted = high_pass.process(shelf.process(sample as f64));
+            self.step_power += weight * weighted * weighted;
+        }
+        self.frames += 1;
+        if self.frames == self.step {
+            let [a, b, c] = self.previous;
+            if self.steps >= 3 {
+                self.blocks
+                    .push((a + b + c + self.step_power) / (4 * self.step) as f64);
+            }
+            self.previous = [b, c, self.step_power];
+            self.steps += 1;
+            self.frames = 0;
+            self.step_power = 0.;
+        }
+    }
+
+    /// The integrated loudness so far, or -inf if there is less than a
+    /// block above the absolute gate.
+    pub fn integrated(&self) -> f64 {
+        let lufs = |power: f64| -0.691 + 10. * power.log10();
+        let mean = |blocks: &mut dyn Iterator<Item = &f64>| {
+            let (sum, count) = blocks.fold((0., 0), |(sum, count), &p| (sum + p, count + 1));
+            (count > 0).then(|| sum / count as f64)
+        };
+        let Some(absolute) = mean(&mut self.blocks.iter().filter(|&&p| lufs(p) > -70.)) else {
+            return f64::NEG_INFINITY;
+        };
+        let relative = lufs(absolute) - 10.;
+        let gated = mean(
+            &mut self
+                .blocks
+                .iter()
+                .filter(|&&p| lufs(p) > -70. && lufs(p) > relative),
+        );
+        gated.map_or(f64::NEG_INFINITY, lufs)
+    }
+}
+
+/// Finds the sound between silences, a frame at a time: stretches where every
+/// channel stays under a threshold for long enough split it into parts.
+pub struct Segments {
+    threshold: f32,
+    min_frames: u64,
+    position: u64,
+    /// Where the part being found starts.
+    start: u64,
+    /// Where the current stretch of silence started, if in one.
+    silence: Option<u64>,
+    segments: Vec<Range<u64>>,
+}
+
+impl Segments {
+    pub fn new(threshold_db: f64, min_frames: u64) -> Self {
+        Segments {
+            threshold: 10f64.powf(threshold_db / 20.) as f32,
+            min_frames: min_frames.max(1),
+            position: 0,
+            start: 0,
+            silence: None,
+            segments: Vec::new(),
+        }
+    }
+
+    pub fn add_frame(&mut self, frame: &[f32]) {
+        if frame.iter().all(|sample| sample.abs() < self.threshold) {
+            self.silence.get_or_insert(self.position);
+        } else if let Some(silence) = self.silence.take() {
+            if self.position - silence >= self.min_frames {
+                if silence > self.start {
+                    self.segments.push(self.start..silence);
+                }
+                self.start = self.position;
+            }
+        }
+        self.position += 1;
+    }
+
+    /// The frames of every part, in order.
+    pub fn finish(mut self) -> Vec<Range<u64>> {
+        let end = match self.silence {
+            Some(silence) if self.position - silence >= self.min_frames => silence,
+            _ => self.position,
+        };
+        if end > self.start {
+            self.segments.push(self.start..end);
+        }
+        self.segments
+    }
+}
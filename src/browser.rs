@@ -0,0 +1,344 @@
+use anyhow::Result;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crossterm::event::{self, KeyCode, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use ratatui::style::Modifier;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{
+    layout::{Constraint, Direction, Layout},
+    prelude::{CrosstermBackend, Terminal},
+    style::{Color, Style},
+};
+use std::io::stdout;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::convert::float_output_stream;
+use crate::decode::{probe, read_audio, resample, Info};
+use crate::playback::output_host;
+use crate::playlist::is_playable;
+use crate::routing::Routing;
+
+/// A subdirectory or playable file of the directory being browsed.
+struct Entry {
+    path: PathBuf,
+    /// The file name, with anything that isn't UTF-8 replaced.
+    name: String,
+    is_dir: bool,
+}
+
+/// The entries of `directory`: its parent, then subdirectories and playable
+/// files in name order. Hidden entries are left out.
+fn list(directory: &Path) -> Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let is_dir = path.is_dir();
+        if !name.starts_with('.') && (is_dir || is_playable(&path)) {
+            entries.push(Entry { path, name, is_dir });
+        }
+    }
+    entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    if let Some(parent) = directory.parent() {
+        entries.insert(
+            0,
+            Entry {
+                path: parent.to_path_buf(),
+                name: "..".to_string(),
+                is_dir: true,
+            },
+        );
+    }
+    Ok(entries)
+}
+
+/// A file playing on the output device until dropped.
+struct Preview {
+    path: PathBuf,
+    _stream: cpal::Stream,
+    position: Arc<AtomicUsize>,
+    length: usize,
+    sample_rate: f64,
+}
+
+impl Preview {
+    fn start(device: &cpal::Device, path: &Path) -> Result<Self> {
+        let config = device.default_output_config()?;
+        let outputs = config.channels() as usize;
+        let sample_rate = config.sample_rate().0 as f64;
+        let (source_sr, file_data) = read_audio(path, None)?;
+        let data = resample(&file_data, source_sr as f64, sample_rate);
+        let length = data[0].len();
+        let routing = Routing::standard(data.len(), outputs);
+
+        let position = Arc::new(AtomicUsize::new(0));
+        let position_for_stream = position.clone();
+        let mut frame = vec![0.; data.len()];
+        let stream = float_output_stream(
+            device,
+            config,
+            false,
+            move |out, _| {
+                let mut position = position_for_stream.load(Ordering::Relaxed);
+                for out in out.chunks_mut(outputs) {
+                    for (sample, channel) in frame.iter_mut().zip(&data) {
+                        *sample = channel.get(position).copied().unwrap_or(0.);
+                    }
+                    routing.apply(&frame, out);
+                    position = (position + 1).min(length);
+                }
+                position_for_stream.store(position, Ordering::Relaxed);
+            },
+            |_| {},
+        )?;
+        stream.play()?;
+        Ok(Preview {
+            path: path.to_path_buf(),
+            _stream: stream,
+            position,
+            length,
+            sample_rate,
+        })
+    }
+}
+
+/// Raw mode on the alternate screen, left again when dropped so that an error
+/// can't leave the terminal unusable.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Result<Self> {
+        enable_raw_mode()?;
+        let screen = Screen;
+        execute!(stdout(), EnterAlternateScreen)?;
+        Ok(screen)
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(stdout(), LeaveAlternateScreen);
+    }
+}
+
+/// Lets the user pick a file to play, starting in `directory`: arrow keys and
+/// ENTER navigate, typing filters the entries fuzzily and TAB previews the
+/// highlighted file on output device `device` of the host `play` would use.
+/// `None` if cancelled.
+pub fn browse(directory: &Path, device: Option<u8>, jack: bool) -> Result<Option<PathBuf>> {
+    let host = output_host(jack);
+    let device = match device {
+        Some(index) => host.output_devices()?.nth(index as usize),
+        None => host.default_output_device(),
+    };
+
+    let matcher = SkimMatcherV2::default();
+    let mut directory = directory.canonicalize()?;
+    let mut entries = list(&directory);
+    let mut filter = String::new();
+    let mut state = ListState::default().with_selected(Some(0));
+    let mut info: Option<(PathBuf, Result<Info>)> = None;
+    let mut preview: Option<Preview> = None;
+    let mut message = String::new();
+
+    let screen = Screen::enter()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    terminal.hide_cursor()?;
+
+    let chosen = loop {
+        // The entries matching the filter, best match first.
+        let shown: Vec<&Entry> = match &entries {
+            Ok(entries) if filter.is_empty() => entries.iter().collect(),
+            Ok(entries) => {
+                let mut scored: Vec<(i64, &Entry)> = entries
+                    .iter()
+                    .filter_map(|entry| Some((matcher.fuzzy_match(&entry.name, &filter)?, entry)))
+                    .collect();
+                scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+                scored.into_iter().map(|(_, entry)| entry).collect()
+            }
+            Err(_) => Vec::new(),
+        };
+        let selected = state
+            .selected()
+            .unwrap_or(0)
+            .min(shown.len().saturating_sub(1));
+        state.select(Some(selected));
+        let highlighted = shown.get(selected).copied();
+
+        if let Some(entry) = highlighted.filter(|entry| !entry.is_dir) {
+            if info.as_ref().map(|(path, _)| path) != Some(&entry.path) {
+                info = Some((entry.path.clone(), probe(&entry.path)));
+            }
+        }
+        let details = match (highlighted, &info) {
+            (Some(entry), _) if entry.is_dir => vec![Line::from("directory")],
+            (Some(_), Some((_, Ok(info)))) => vec![
+                Line::from(format!("duration  {:.2}s", info.duration())),
+                Line::from(format!("rate      {} Hz", info.sample_rate)),
+                Line::from(format!("channels  {}", info.channels)),
+                Line::from(format!("bits      {}", info.bits_per_sample)),
+            ],
+            (Some(_), Some((_, Err(err)))) => vec![Line::from(format!("unreadable: {}", err))],
+            _ => Vec::new(),
+        };
+        let previewing = preview.as_ref().map(|preview| {
+            format!(
+                "previewing {}  {:.1}s/{:.1}s",
+                preview
+                    .path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                preview.position.load(Ordering::Relaxed) as f64 / preview.sample_rate,
+                preview.length as f64 / preview.sample_rate
+            )
+        });
+
+        terminal.draw(|f| {
+            let [title, filter_area, body, message_area, help] = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                    Constraint::Min(3),
+                    Constraint::Length(1),
+                    Constraint::Length(2),
+                ])
+                .areas(f.size());
+            f.render_widget(
+                Paragraph::new(directory.to_string_lossy().into_owned()).style(
+                    Style::default()
+                        .fg(Color::Blue)
+                        .add_modifier(Modifier::BOLD),
+                ),
+                title,
+            );
+            f.render_widget(Paragraph::new(format!("> {}", filter)), filter_area);
+
+            let [list_area, info_area] =
+                Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                    .areas(body);
+            let items: Vec<ListItem> = match &entries {
+                Ok(_) => shown
+                    .iter()
+                    .map(|entry| {
+                        if entry.is_dir {
+                            ListItem::new(format!("{}/", entry.name))
+                                .style(Style::default().fg(Color::Cyan))
+                        } else {
+                            ListItem::new(entry.name.clone())
+                        }
+                    })
+                    .collect(),
+                Err(err) => vec![ListItem::new(format!("cannot read directory: {}", err))
+                    .style(Style::default().fg(Color::Red))],
+            };
+            let list = List::new(items)
+                .block(Block::default().borders(Borders::RIGHT))
+                .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+            f.render_stateful_widget(list, list_area, &mut state);
+            let mut lines = details;
+            if let Some(previewing) = &previewing {
+                lines.push(Line::from(""));
+                lines.push(Line::from(Span::styled(
+                    previewing.clone(),
+                    Style::default().fg(Color::Green),
+                )));
+            }
+            f.render_widget(
+                Paragraph::new(lines).block(Block::default().borders(Borders::NONE).title(" info")),
+                info_area,
+            );
+
+            f.render_widget(
+                Paragraph::new(message.clone()).style(Style::default().fg(Color::Red)),
+                message_area,
+            );
+            f.render_widget(
+                Paragraph::new(Span::styled(
+                    "type to filter, UP/DOWN to move, ENTER to open a directory or play a file, BACKSPACE to go up, TAB to preview, ESC to cancel.",
+                    Style::default()
+                        .fg(Color::Yellow)
+                        .add_modifier(Modifier::ITALIC | Modifier::BOLD),
+                ))
+                .wrap(Wrap { trim: true }),
+                help,
+            );
+        })?;
+
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let event::Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+        message.clear();
+        let mut go_to = None;
+        match key.code {
+            KeyCode::Esc if !filter.is_empty() => filter.clear(),
+            KeyCode::Esc => break None,
+            KeyCode::Up => state.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down => state.select(Some(selected + 1)),
+            KeyCode::PageUp => state.select(Some(selected.saturating_sub(10))),
+            KeyCode::PageDown => state.select(Some(selected + 10)),
+            KeyCode::Enter => match highlighted {
+                Some(entry) if entry.is_dir => go_to = Some(entry.path.clone()),
+                Some(entry) => break Some(entry.path.clone()),
+                None => {}
+            },
+            KeyCode::Backspace if filter.is_empty() => {
+                go_to = directory.parent().map(Path::to_path_buf);
+            }
+            KeyCode::Backspace => {
+                filter.pop();
+            }
+            KeyCode::Tab => match (highlighted, &device) {
+                (Some(entry), _) if entry.is_dir => {}
+                (Some(entry), Some(device))
+                    if preview.as_ref().map(|preview| &preview.path) != Some(&entry.path) =>
+                {
+                    preview = None;
+                    match Preview::start(device, &entry.path) {
+                        Ok(started) => preview = Some(started),
+                        Err(err) => message = format!("cannot preview {}: {}", entry.name, err),
+                    }
+                }
+                (Some(_), None) => message = "no output device to preview on".to_string(),
+                _ => preview = None,
+            },
+            KeyCode::Char(c) => {
+                filter.push(c);
+                state.select(Some(0));
+            }
+            _ => {}
+        }
+        if let Some(path) = go_to {
+            directory = path;
+            entries = list(&directory);
+            filter.clear();
+            state.select(Some(0));
+        }
+    };
+
+    drop(preview);
+    drop(terminal);
+    drop(screen);
+    Ok(chosen)
+}
@@ -0,0 +1,102 @@
+use hound::{SampleFormat, WavSpec};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Offset of the `data` chunk size field: file header (8), `desc` chunk (12 + 32)
+/// and the `data` chunk type (4).
+const DATA_LEN_OFFSET: u64 = 56;
+/// Everything in front of the samples, including the `data` chunk's edit count.
+const HEADER_LEN: u64 = DATA_LEN_OFFSET + 8 + 4;
+
+/// A streaming Core Audio Format writer for little-endian linear PCM.
+///
+/// CAF sizes are 64-bit, so there is no 4 GiB limit, and the size of the final
+/// `data` chunk may be left as -1 ("until the end of the file"), which is what
+/// it is while recording. An interrupted recording is therefore always a valid
+/// file; the size is only filled in by [`finalize`](Self::finalize).
+pub struct CafWriter {
+    writer: BufWriter<File>,
+    spec: WavSpec,
+    data_bytes: u64,
+}
+
+impl CafWriter {
+    pub fn create(path: &Path, spec: WavSpec) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+
+        writer.write_all(b"caff")?;
+        writer.write_all(&1u16.to_be_bytes())?;
+        writer.write_all(&0u16.to_be_bytes())?;
+
+        let bytes_per_sample = (spec.bits_per_sample / 8) as u32;
+        // kCAFLinearPCMFormatFlagIsLittleEndian, plus IsFloat for float data.
+        let flags: u32 = match spec.sample_format {
+            SampleFormat::Float => 0b11,
+            SampleFormat::Int => 0b10,
+        };
+        writer.write_all(b"desc")?;
+        writer.write_all(&32i64.to_be_bytes())?;
+        writer.write_all(&(spec.sample_rate as f64).to_be_bytes())?;
+        writer.write_all(b"lpcm")?;
+        writer.write_all(&flags.to_be_bytes())?;
+        writer.write_all(&(bytes_per_sample * spec.channels as u32).to_be_bytes())?;
+        writer.write_all(&1u32.to_be_bytes())?;
+        writer.write_all(&(spec.channels as u32).to_be_bytes())?;
+        writer.write_all(&(spec.bits_per_sample as u32).to_be_bytes())?;
+
+        writer.write_all(b"data")?;
+        writer.write_all(&(-1i64).to_be_bytes())?;
+        writer.write_all(&0u32.to_be_bytes())?;
+
+        Ok(CafWriter {
+            writer,
+            spec,
+            data_bytes: 0,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        HEADER_LEN + self.data_bytes
+    }
+
+    pub fn block_align(&self) -> u64 {
+        self.spec.channels as u64 * (self.spec.bits_per_sample / 8) as u64
+    }
+
+    /// Writes interleaved integer samples, already at the file's bit depth.
+    pub fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        let bytes_per_sample = (self.spec.bits_per_sample / 8) as usize;
+        for &sample in samples {
+            self.writer
+                .write_all(&sample.to_le_bytes()[..bytes_per_sample])?;
+        }
+        self.data_bytes += (samples.len() * bytes_per_sample) as u64;
+        Ok(())
+    }
+
+    pub fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        for &sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_bytes += samples.len() as u64 * 4;
+        Ok(())
+    }
+
+    /// The data chunk size stays -1 until the file is finalized, so keeping the
+    /// file readable only takes getting the samples to disk.
+    pub fn update_header(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> anyhow::Result<()> {
+        self.writer.seek(SeekFrom::Start(DATA_LEN_OFFSET))?;
+        // The chunk size includes the 4-byte edit count.
+        self.writer
+            .write_all(&(self.data_bytes as i64 + 4).to_be_bytes())?;
+        self.writer.flush()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
@@ -1,4 +1,5 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use std::time::Duration;
 
 /// Audio Swiss Army knife written in Rust. Like Sox but interactive with TUI.
 #[derive(Parser, Debug)]
@@ -20,6 +21,10 @@ pub struct Cli {
     #[arg(short, long)]
     #[allow(dead_code)]
     pub jack: bool,
+
+    /// Append stream errors, overruns and underruns to this file, e.g. `session.log`
+    #[arg(long, global = true)]
+    pub log: Option<String>,
 }
 
 #[derive(Debug, Subcommand)]
@@ -28,8 +33,24 @@ pub enum Commands {
     Rec(RecArgs),
     /// Play an audio file
     Play(PlayArgs),
+    /// Play a backing track and record against it
+    Overdub(OverdubArgs),
     /// Monitor audio input with scopes
     Monitor(MonitorArgs),
+    /// Rebuild the header of a wav file left unfinished by an interrupted recording
+    Repair(RepairArgs),
+    /// Print signal statistics of audio files: levels, DC offset, noise floor and more
+    Stats(StatsArgs),
+    /// Keep only the part of a file between two times
+    Trim(TrimArgs),
+    /// Fade a file in and out
+    Fade(FadeArgs),
+    /// Change the volume of a file by a number of dB
+    Gain(GainArgs),
+    /// Bring a file to a peak level or an integrated loudness
+    Normalize(NormalizeArgs),
+    /// Cut a long recording into a file for every stretch between silences
+    SplitSilence(SplitArgs),
     /// List available audio devices
     List,
 }
@@ -37,23 +58,316 @@ pub enum Commands {
 /// Arguments used for the `rec` command
 #[derive(Args, Debug)]
 pub struct RecArgs {
-    /// Path for the output audio file, e.g. `output`
+    /// Path for the output audio file, e.g. `output`; `-` streams to stdout
     #[arg(required = false)]
     pub output: Option<String>,
-    /// The audio device index to use
+    /// The audio device index to use; repeat to record from several devices at once,
+    /// e.g. `-d 1 -d 3`
     #[arg(required = false, short, long)]
-    pub device: Option<u8>,
+    pub device: Vec<u8>,
+    /// With several devices, write each device's channels to its own file, e.g. `output_dev3.wav`
+    #[arg(long, conflicts_with = "split_channels")]
+    pub file_per_device: bool,
+    /// File format to record to; defaults to the output extension, or wav in the device's sample format
+    #[arg(required = false, short, long, value_enum)]
+    pub format: Option<RecFormat>,
+    /// Write headerless PCM, the same as `--format raw`
+    #[arg(long, conflicts_with = "format")]
+    pub raw: bool,
+    /// Sample encoding of raw output, e.g. `s16be` or `mulaw`; defaults to the device's sample format
+    #[arg(long, value_enum)]
+    pub encoding: Option<Encoding>,
+    /// Input channels to record, counting from 1, e.g. `3,4`; defaults to all channels.
+    /// With several devices, channels are numbered across them in the order given
+    #[arg(long, value_delimiter = ',', value_parser = clap::value_parser!(u16).range(1..))]
+    pub channels_map: Vec<u16>,
+    /// Write each recorded channel to its own mono file, e.g. `output_ch3.wav`
+    #[arg(long)]
+    pub split_channels: bool,
+    /// Start a new numbered file after this much audio, e.g. `30m`, `1h` or `1h30m`
+    #[arg(long, value_parser = parse_duration)]
+    pub split_every: Option<Duration>,
+    /// Start a new numbered file before the current one exceeds this size, e.g. `2GB` or `500MiB`
+    #[arg(long, value_parser = parse_size)]
+    pub split_size: Option<u64>,
+    /// Also write the take summary as JSON next to the recording, e.g. `output.json`
+    #[arg(long)]
+    pub summary_json: bool,
+    /// How often the file header is updated, so an interrupted recording stays readable
+    #[arg(long, value_parser = parse_duration, default_value = "5s")]
+    pub flush_interval: Duration,
+    /// How much audio is buffered for the disk before input is dropped, e.g. `10s` or `1m`
+    #[arg(long, value_parser = parse_duration, default_value = "10s")]
+    pub queue: Duration,
+}
+
+/// File formats the `rec` command can write
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum RecFormat {
+    /// 16-bit integer wav
+    Wav16,
+    /// 24-bit integer wav
+    Wav24,
+    /// 32-bit integer wav
+    Wav32,
+    /// 32-bit float wav
+    Wav32f,
+    /// FLAC, encoded while recording; 16-bit for 16-bit devices, 24-bit otherwise
+    Flac,
+    /// Core Audio Format in the device's sample format
+    Caf,
+    /// Headerless PCM in `--encoding`, or the device's sample format
+    Raw,
+}
+
+/// Sample encodings of headerless PCM
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Encoding {
+    /// 8-bit unsigned integer
+    U8,
+    /// 16-bit signed integer, little-endian
+    S16le,
+    /// 16-bit signed integer, big-endian
+    S16be,
+    /// 24-bit signed integer, little-endian
+    S24le,
+    /// 24-bit signed integer, big-endian
+    S24be,
+    /// 32-bit signed integer, little-endian
+    S32le,
+    /// 32-bit signed integer, big-endian
+    S32be,
+    /// 32-bit float, little-endian
+    F32le,
+    /// 32-bit float, big-endian
+    F32be,
+    /// 8-bit G.711 μ-law
+    Mulaw,
+    /// 8-bit G.711 A-law
+    Alaw,
+}
+
+/// Arguments describing headerless PCM input
+#[derive(Args, Debug)]
+pub struct RawArgs {
+    /// Read the input as headerless PCM, described by `--rate`, `--channels` and `--encoding`
+    #[arg(long, requires = "rate")]
+    pub raw: bool,
+    /// Sample rate of raw input, e.g. `16000`
+    #[arg(long, requires = "raw")]
+    pub rate: Option<u32>,
+    /// Channels of raw input [default: 2]
+    #[arg(long, requires = "raw", value_parser = clap::value_parser!(u16).range(1..))]
+    pub channels: Option<u16>,
+    /// Sample encoding of raw input [default: s16le]
+    #[arg(long, requires = "raw", value_enum)]
+    pub encoding: Option<Encoding>,
 }
 
 /// Arguments used for the `play` command
 #[derive(Args, Debug)]
 pub struct PlayArgs {
-    /// Path to the audio file to play; must be wav format for now, e.g. `input.wav`
+    /// Wav or flac files to play in order, e.g. `a.wav b.flac`; directories are searched
+    /// recursively and `-` reads stdin. Without any, a file is picked interactively
     #[arg(required = false)]
-    pub input: Option<String>,
+    pub inputs: Vec<String>,
     /// The audio device index to use
     #[arg(required = false, short, long)]
     pub device: Option<u8>,
+    /// Loop the file until stopped
+    #[arg(long = "loop", conflicts_with = "loop_count")]
+    pub looping: bool,
+    /// Play the file this many times, e.g. `3`
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub loop_count: Option<u64>,
+    /// Playback speed without changing pitch, from 0.25 to 2, e.g. `0.75`
+    #[arg(long, value_parser = parse_speed, default_value = "1")]
+    pub speed: f64,
+    /// Shift the pitch by this many semitones without changing speed, e.g. `-2` or `0.5`
+    #[arg(long, value_parser = parse_pitch, default_value = "0", allow_negative_numbers = true)]
+    pub pitch: f64,
+    /// Playback volume in dB, from -60 to 12, e.g. `-6`; change it live with the up and down keys
+    #[arg(long, value_parser = parse_gain, default_value = "0", allow_negative_numbers = true)]
+    pub gain: f32,
+    /// Play file channels on device outputs, as `channel:output` pairs counting from 1,
+    /// e.g. `1:2,2:1` to swap left and right; by default channels are up- or downmixed to fit
+    #[arg(long, value_delimiter = ',', value_parser = parse_route)]
+    pub map: Vec<(u16, u16)>,
+    /// Add TPDF dither when the output device takes integer samples
+    #[arg(long)]
+    pub dither: bool,
+    /// Play the files in random order
+    #[arg(long)]
+    pub shuffle: bool,
+    /// Start the playlist over after the last file
+    #[arg(long)]
+    pub repeat: bool,
+    #[command(flatten)]
+    pub raw: RawArgs,
+    /// Fade from one file into the next over this long, e.g. `2s`; by default files
+    /// follow each other without a gap
+    #[arg(long, value_parser = parse_duration)]
+    pub crossfade: Option<Duration>,
+}
+
+/// Arguments used for the `overdub` command
+#[derive(Args, Debug)]
+pub struct OverdubArgs {
+    /// Wav, flac or raw backing track to play, e.g. `backing.wav`; `-` reads stdin
+    pub backing: String,
+    /// Path for the recorded take, e.g. `take.wav`
+    pub output: String,
+    /// The input device index to record from
+    #[arg(required = false, short, long)]
+    pub input_device: Option<u8>,
+    /// The output device index to play the backing track on
+    #[arg(required = false, short, long)]
+    pub output_device: Option<u8>,
+    /// Round-trip latency to compensate, in milliseconds; measured from the stream timestamps if not given
+    #[arg(required = false, short, long, value_parser = parse_latency)]
+    pub latency: Option<f64>,
+    /// Mix the input into the output, to hear yourself while recording
+    #[arg(short, long)]
+    pub monitor: bool,
+    /// File format of the take; defaults to the output extension, or 32-bit float wav
+    #[arg(required = false, short, long, value_enum)]
+    pub format: Option<RecFormat>,
+    #[command(flatten)]
+    pub raw: RawArgs,
+}
+
+/// Arguments used for the `repair` command
+#[derive(Args, Debug)]
+pub struct RepairArgs {
+    /// Path to the damaged wav file, e.g. `broken.wav`
+    pub input: String,
+    /// Write the repaired file here instead of fixing the input in place
+    #[arg(required = false, short, long)]
+    pub output: Option<String>,
+}
+
+/// Arguments used for the `stats` command
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    /// Wav, flac or raw files to analyse, e.g. `take.wav`; `-` reads stdin
+    #[arg(required = true)]
+    pub inputs: Vec<String>,
+    /// Print the statistics as JSON
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub raw: RawArgs,
+}
+
+/// The input and output of the editing commands
+#[derive(Args, Debug)]
+pub struct EditArgs {
+    /// File to edit, e.g. `take.wav`; `-` reads stdin
+    pub input: String,
+    /// Where to write the result, in the format of the input, e.g. `edited.wav`;
+    /// `-` writes to stdout
+    pub output: String,
+    #[command(flatten)]
+    pub raw: RawArgs,
+}
+
+/// Arguments used for the `trim` command
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("range").required(true).multiple(true)))]
+pub struct TrimArgs {
+    #[command(flatten)]
+    pub edit: EditArgs,
+    /// Where the kept part starts, e.g. `1.5` or `1m30s`; defaults to the start of the file
+    #[arg(long, value_parser = parse_time, group = "range")]
+    pub start: Option<Duration>,
+    /// Where the kept part ends, e.g. `30s`; defaults to the end of the file
+    #[arg(long, value_parser = parse_time, group = "range")]
+    pub end: Option<Duration>,
+}
+
+/// Arguments used for the `fade` command
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("fades").required(true).multiple(true)))]
+pub struct FadeArgs {
+    #[command(flatten)]
+    pub edit: EditArgs,
+    /// Length of the fade in at the start, e.g. `0.5`
+    #[arg(long = "in", value_parser = parse_time, group = "fades")]
+    pub fade_in: Option<Duration>,
+    /// Length of the fade out at the end, e.g. `2s`
+    #[arg(long = "out", value_parser = parse_time, group = "fades")]
+    pub fade_out: Option<Duration>,
+    /// How the volume changes over a fade
+    #[arg(long, value_enum, default_value = "lin")]
+    pub shape: FadeShape,
+}
+
+/// Curves a fade can follow
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum FadeShape {
+    /// Volume changes at a constant rate
+    Lin,
+    /// Level in dB changes at a constant rate, over 60 dB; sounds even to the ear
+    Log,
+    /// Starts and ends gently, like half a cosine
+    Scurve,
+}
+
+/// Arguments used for the `gain` command
+#[derive(Args, Debug)]
+pub struct GainArgs {
+    /// Change in dB, e.g. `-3dB` or `6`
+    #[arg(value_parser = parse_db, allow_hyphen_values = true)]
+    pub gain: f64,
+    #[command(flatten)]
+    pub edit: EditArgs,
+}
+
+/// Arguments used for the `normalize` command
+#[derive(Args, Debug)]
+#[command(group(ArgGroup::new("target").required(true)))]
+pub struct NormalizeArgs {
+    #[command(flatten)]
+    pub edit: EditArgs,
+    /// Bring the highest peak of any channel to this level in dBFS, e.g. `-1dB`
+    #[arg(long, value_parser = parse_db, allow_hyphen_values = true, group = "target")]
+    pub peak: Option<f64>,
+    /// Bring the integrated loudness (EBU R 128) to this level in LUFS, e.g. `-16`
+    #[arg(long, value_parser = parse_db, allow_hyphen_values = true, group = "target")]
+    pub lufs: Option<f64>,
+}
+
+/// Arguments used for the `split-silence` command
+#[derive(Args, Debug)]
+pub struct SplitArgs {
+    /// The file to split, or `-` for stdin
+    pub input: String,
+    /// Level in dBFS that every channel must stay under to count as silence
+    #[arg(long, value_parser = parse_db, allow_hyphen_values = true, default_value = "-50dB")]
+    pub threshold: f64,
+    /// How long silence must last to split there; shorter pauses stay in the parts
+    #[arg(long, value_parser = parse_duration, default_value = "1.5s")]
+    pub min_silence: Duration,
+    /// Files to write the parts to, `{n}` being the part number from 1
+    #[arg(long, default_value = "part_{n}.wav")]
+    pub out: String,
+    /// Print the parts as labels instead of writing them
+    #[arg(long)]
+    pub dry_run: bool,
+    /// How `--dry-run` prints the parts
+    #[arg(long, value_enum, default_value = "audacity")]
+    pub labels: LabelFormat,
+    #[command(flatten)]
+    pub raw: RawArgs,
+}
+
+/// Ways to print the parts found by `split-silence`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum LabelFormat {
+    /// Audacity label track: start and end in seconds, and a name, per line
+    Audacity,
+    /// Cue sheet with a track for every part
+    Cue,
 }
 
 /// Arguments used for the `monitor` command
@@ -63,3 +377,156 @@ pub struct MonitorArgs {
     #[arg(required = false, short, long)]
     pub buffer_size: Option<usize>,
 }
+
+/// Parses durations such as `90`, `45s`, `30m`, `1.5h` or `1h30m`.
+/// A bare number is taken as seconds.
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let duration = parse_time(s)?;
+    if duration.is_zero() {
+        return Err(format!("duration `{}` must be greater than zero", s.trim()));
+    }
+    Ok(duration)
+}
+
+/// Parses a point in or a stretch of a file as [`parse_duration`] does, but
+/// also takes `0`.
+fn parse_time(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let mut total = 0.0;
+    let mut has_unit = false;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() || c == '.' {
+            number.push(c);
+            continue;
+        }
+        let value: f64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration `{s}`"))?;
+        total += value
+            * match c {
+                'h' => 3600.0,
+                'm' => 60.0,
+                's' => 1.0,
+                _ => return Err(format!("invalid duration unit `{c}` in `{s}`")),
+            };
+        has_unit = true;
+        number.clear();
+    }
+    if !number.is_empty() {
+        if has_unit {
+            return Err(format!("missing unit after `{number}` in `{s}`"));
+        }
+        total = number
+            .parse()
+            .map_err(|_| format!("invalid duration `{s}`"))?;
+    }
+    if s.is_empty() {
+        return Err(format!("invalid duration `{s}`"));
+    }
+    Duration::try_from_secs_f64(total).map_err(|e| e.to_string())
+}
+
+/// Parses a latency in milliseconds, e.g. `12.5`.
+fn parse_latency(s: &str) -> Result<f64, String> {
+    let ms: f64 = s
+        .trim()
+        .trim_end_matches("ms")
+        .parse()
+        .map_err(|_| format!("invalid latency `{s}`"))?;
+    if !(0.0..=10_000.0).contains(&ms) {
+        return Err(format!("latency `{s}` must be between 0 and 10000 ms"));
+    }
+    Ok(ms)
+}
+
+/// Parses a playback speed such as `0.75`.
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid speed `{s}`"))?;
+    if !(0.25..=2.0).contains(&speed) {
+        return Err(format!("speed `{s}` must be between 0.25 and 2"));
+    }
+    Ok(speed)
+}
+
+/// Parses a pitch shift in semitones such as `-2` or `0.5`.
+fn parse_pitch(s: &str) -> Result<f64, String> {
+    let semitones: f64 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid pitch `{s}`"))?;
+    if !(-24.0..=24.0).contains(&semitones) {
+        return Err(format!("pitch `{s}` must be between -24 and 24 semitones"));
+    }
+    Ok(semitones)
+}
+
+/// Parses a volume in dB such as `-6`.
+fn parse_gain(s: &str) -> Result<f32, String> {
+    let db: f32 = s
+        .trim()
+        .trim_end_matches("dB")
+        .parse()
+        .map_err(|_| format!("invalid gain `{s}`"))?;
+    if !(-60.0..=12.0).contains(&db) {
+        return Err(format!("gain `{s}` must be between -60 and 12 dB"));
+    }
+    Ok(db)
+}
+
+/// Parses a level or change in dB such as `-3dB` or `6`.
+fn parse_db(s: &str) -> Result<f64, String> {
+    let db: f64 = s
+        .trim()
+        .trim_end_matches("dB")
+        .trim_end_matches("db")
+        .parse()
+        .map_err(|_| format!("invalid level `{s}`, expected e.g. `-3dB`"))?;
+    if !db.is_finite() {
+        return Err(format!("level `{s}` must be a finite number of dB"));
+    }
+    Ok(db)
+}
+
+/// Parses a route such as `1:2`, file channel 1 to output 2.
+fn parse_route(s: &str) -> Result<(u16, u16), String> {
+    let invalid = || format!("invalid route `{s}`, expected e.g. `1:2`");
+    let (channel, output) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let channel: u16 = channel.parse().map_err(|_| invalid())?;
+    let output: u16 = output.parse().map_err(|_| invalid())?;
+    if channel == 0 || output == 0 {
+        return Err(format!("route `{s}` counts channels from 1"));
+    }
+    Ok((channel, output))
+}
+
+/// Parses sizes such as `4096`, `500MB`, `2GB` or `1.5GiB`.
+/// `KB`/`MB`/`GB` are decimal, `KiB`/`MiB`/`GiB` are binary.
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (number, unit) = s.split_at(split);
+    let number: f64 = number.parse().map_err(|_| format!("invalid size `{s}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "t" | "tb" => 1_000_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        "tib" => 1 << 40,
+        _ => return Err(format!("invalid size unit `{unit}` in `{s}`")),
+    };
+    let size = (number * multiplier as f64) as u64;
+    if size == 0 {
+        return Err(format!("size `{s}` must be greater than zero"));
+    }
+    Ok(size)
+}
@@ -0,0 +1,361 @@
+use anyhow::{anyhow, Result};
+use cpal::traits::DeviceTrait;
+use cpal::{
+    FromSample, InputCallbackInfo, OutputCallbackInfo, Sample, SampleFormat, SizedSample, Stream,
+    StreamError, SupportedStreamConfig,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+/// A buffer of interleaved samples in the device's native format.
+pub enum Samples {
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    F32(Vec<f32>),
+}
+
+impl Samples {
+    /// Extracts the channels at `indices` out of `channels` interleaved ones.
+    pub fn channels(&self, indices: &[usize], channels: usize) -> Samples {
+        fn pick<T: Copy>(samples: &[T], indices: &[usize], channels: usize) -> Vec<T> {
+            samples
+                .chunks_exact(channels)
+                .flat_map(|frame| indices.iter().map(move |&index| frame[index]))
+                .collect()
+        }
+        match self {
+            Samples::I16(samples) => Samples::I16(pick(samples, indices, channels)),
+            Samples::I32(samples) => Samples::I32(pick(samples, indices, channels)),
+            Samples::F32(samples) => Samples::F32(pick(samples, indices, channels)),
+        }
+    }
+
+    /// Appends the samples as floats in `-1.0..1.0`. Exact for 16-bit and
+    /// float input.
+    pub fn to_float(&self, out: &mut Vec<f32>) {
+        match self {
+            Samples::I16(samples) => {
+                out.extend(samples.iter().map(|&s| s as f32 / 32768.0));
+            }
+            Samples::I32(samples) => {
+                out.extend(samples.iter().map(|&s| (s as f64 / 2147483648.0) as f32));
+            }
+            Samples::F32(samples) => out.extend_from_slice(samples),
+        }
+    }
+}
+
+/// Converts native samples to integers of a given bit depth.
+///
+/// Widening (e.g. 16-bit device to 24-bit file) is a lossless shift. Anything
+/// that loses resolution, including float to integer, gets triangular (TPDF)
+/// dither of +-1 LSB of the target depth, so the quantization error is
+/// uncorrelated noise rather than distortion.
+pub struct Quantizer {
+    bits: u32,
+    rng: StdRng,
+    dither: bool,
+}
+
+impl Quantizer {
+    pub fn new(bits: u16) -> Self {
+        Quantizer {
+            bits: bits as u32,
+            rng: StdRng::from_entropy(),
+            dither: true,
+        }
+    }
+
+    /// Rounds without dither, for samples that are still on the steps of
+    /// the target depth, e.g. cut out of a file of that depth.
+    pub fn exact(bits: u16) -> Self {
+        Quantizer {
+            dither: false,
+            ..Quantizer::new(bits)
+        }
+    }
+
+    pub fn quantize(&mut self, samples: &Samples, out: &mut Vec<i32>) {
+        match samples {
+            Samples::I16(samples) => self.requantize(samples.iter().map(|&s| s as i32), 16, out),
+            Samples::I32(samples) => self.requantize(samples.iter().copied(), 32, out),
+            Samples::F32(samples) => self.quantize_float(samples, out),
+        }
+    }
+
+    /// Quantizes floats in `-1.0..1.0`, clamping anything beyond full scale.
+    pub fn quantize_float(&mut self, samples: &[f32], out: &mut Vec<i32>) {
+        let scale = (1u64 << (self.bits - 1)) as f64;
+        for &sample in samples {
+            let dithered = sample as f64 * scale + self.tpdf();
+            out.push(self.clamp(dithered.round()));
+        }
+    }
+
+    fn requantize(&mut self, samples: impl Iterator<Item = i32>, bits: u32, out: &mut Vec<i32>) {
+        if bits <= self.bits {
+            let shift = self.bits - bits;
+            out.extend(samples.map(|s| s << shift));
+        } else {
+            let scale = (1u64 << (bits - self.bits)) as f64;
+            for sample in samples {
+                let dithered = sample as f64 / scale + self.tpdf();
+                out.push(self.clamp(dithered.round()));
+            }
+        }
+    }
+
+    /// Triangular noise in `-1.0..1.0` LSB: the difference of two uniform values.
+    fn tpdf(&mut self) -> f64 {
+        if !self.dither {
+            return 0.;
+        }
+        self.rng.gen::<f64>() - self.rng.gen::<f64>()
+    }
+
+    fn clamp(&self, value: f64) -> i32 {
+        let max = ((1i64 << (self.bits - 1)) - 1) as f64;
+        value.clamp(-max - 1.0, max) as i32
+    }
+}
+
+/// A sample type an output device may take.
+pub trait OutputSample: SizedSample {
+    /// Bits of resolution of an integer format, `None` for float.
+    const BITS: Option<u32>;
+    /// From a float in `-1.0..1.0`, or for integers a whole number already
+    /// in the signed range of `BITS`.
+    fn from_f64(value: f64) -> Self;
+}
+
+macro_rules! signed_output {
+    ($($t:ty),*) => {$(
+        impl OutputSample for $t {
+            const BITS: Option<u32> = Some(<$t>::BITS);
+            fn from_f64(value: f64) -> Self {
+                value as $t
+            }
+        }
+    )*};
+}
+
+macro_rules! unsigned_output {
+    ($($t:ty),*) => {$(
+        impl OutputSample for $t {
+            const BITS: Option<u32> = Some(<$t>::BITS);
+            // Offset binary: silence is the middle of the range.
+            fn from_f64(value: f64) -> Self {
+                (value + (1u64 << (<$t>::BITS - 1)) as f64) as $t
+            }
+        }
+    )*};
+}
+
+signed_output!(i8, i16, i32, i64);
+unsigned_output!(u8, u16, u32, u64);
+
+impl OutputSample for f32 {
+    const BITS: Option<u32> = None;
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+impl OutputSample for f64 {
+    const BITS: Option<u32> = None;
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+}
+
+/// Converts floats in `-1.0..1.0` to any output sample format.
+///
+/// Anything beyond full scale saturates instead of wrapping around. Integer
+/// formats can get TPDF dither of +-1 LSB, as in `Quantizer`.
+pub struct Converter {
+    dither: Option<StdRng>,
+}
+
+impl Converter {
+    pub fn new(dither: bool) -> Self {
+        Converter {
+            dither: dither.then(StdRng::from_entropy),
+        }
+    }
+
+    pub fn convert<T: OutputSample>(&mut self, sample: f32) -> T {
+        let sample = sample as f64;
+        match T::BITS {
+            None => T::from_f64(sample.clamp(-1., 1.)),
+            Some(bits) => {
+                let scale = (1u64 << (bits - 1)) as f64;
+                let mut value = sample * scale;
+                if let Some(rng) = &mut self.dither {
+                    value += rng.gen::<f64>() - rng.gen::<f64>();
+                }
+                T::from_f64(value.round().clamp(-scale, scale - 1.))
+            }
+        }
+    }
+}
+
+/// Opens an input stream in the device's sample format and hands each buffer
+/// to `callback` as floats.
+pub fn float_input_stream(
+    device: &cpal::Device,
+    config: SupportedStreamConfig,
+    callback: impl FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+    err_fn: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream> {
+    fn build<T>(
+        device: &cpal::Device,
+        config: SupportedStreamConfig,
+        mut callback: impl FnMut(&[f32], &InputCallbackInfo) + Send + 'static,
+        err_fn: impl FnMut(StreamError) + Send + 'static,
+    ) -> Result<Stream>
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        let mut buffer = Vec::new();
+        let stream = device.build_input_stream(
+            &config.into(),
+            move |data: &[T], info: &InputCallbackInfo| {
+                buffer.clear();
+                buffer.extend(data.iter().map(|&sample| f32::from_sample(sample)));
+                callback(&buffer, info);
+            },
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+
+    match config.sample_format() {
+        SampleFormat::I8 => build::<i8>(device, config, callback, err_fn),
+        SampleFormat::I16 => build::<i16>(device, config, callback, err_fn),
+        SampleFormat::I32 => build::<i32>(device, config, callback, err_fn),
+        SampleFormat::I64 => build::<i64>(device, config, callback, err_fn),
+        SampleFormat::U8 => build::<u8>(device, config, callback, err_fn),
+        SampleFormat::U16 => build::<u16>(device, config, callback, err_fn),
+        SampleFormat::U32 => build::<u32>(device, config, callback, err_fn),
+        SampleFormat::U64 => build::<u64>(device, config, callback, err_fn),
+        SampleFormat::F32 => build::<f32>(device, config, callback, err_fn),
+        SampleFormat::F64 => build::<f64>(device, config, callback, err_fn),
+        sample_format => Err(anyhow!("Unsupported sample format '{sample_format}'")),
+    }
+}
+
+/// Opens an output stream in the device's sample format, filled by `fill`
+/// with floats, converted as `Converter` does.
+pub fn float_output_stream(
+    device: &cpal::Device,
+    config: SupportedStreamConfig,
+    dither: bool,
+    fill: impl FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+    err_fn: impl FnMut(StreamError) + Send + 'static,
+) -> Result<Stream> {
+    fn build<T: OutputSample>(
+        device: &cpal::Device,
+        config: SupportedStreamConfig,
+        dither: bool,
+        mut fill: impl FnMut(&mut [f32], &OutputCallbackInfo) + Send + 'static,
+        err_fn: impl FnMut(StreamError) + Send + 'static,
+    ) -> Result<Stream> {
+        let mut converter = Converter::new(dither);
+        let mut buffer = Vec::new();
+        let stream = device.build_output_stream(
+            &config.into(),
+            move |data: &mut [T], info: &OutputCallbackInfo| {
+                buffer.resize(data.len(), 0.);
+                fill(&mut buffer, info);
+                for (out, &sample) in data.iter_mut().zip(&buffer) {
+                    *out = converter.convert(sample);
+                }
+            },
+            err_fn,
+            None,
+        )?;
+        Ok(stream)
+    }
+
+    match config.sample_format() {
+        SampleFormat::I8 => build::<i8>(device, config, dither, fill, err_fn),
+        SampleFormat::I16 => build::<i16>(device, config, dither, fill, err_fn),
+        SampleFormat::I32 => build::<i32>(device, config, dither, fill, err_fn),
+        SampleFormat::I64 => build::<i64>(device, config, dither, fill, err_fn),
+        SampleFormat::U8 => build::<u8>(device, config, dither, fill, err_fn),
+        SampleFormat::U16 => build::<u16>(device, config, dither, fill, err_fn),
+        SampleFormat::U32 => build::<u32>(device, config, dither, fill, err_fn),
+        SampleFormat::U64 => build::<u64>(device, config, dither, fill, err_fn),
+        SampleFormat::F32 => build::<f32>(device, config, dither, fill, err_fn),
+        SampleFormat::F64 => build::<f64>(device, config, dither, fill, err_fn),
+        sample_format => Err(anyhow!("Unsupported sample format '{sample_format}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signed_saturates_at_full_scale() {
+        let mut converter = Converter::new(false);
+        assert_eq!(converter.convert::<i8>(1.), i8::MAX);
+        assert_eq!(converter.convert::<i8>(-1.), i8::MIN);
+        assert_eq!(converter.convert::<i16>(1.), i16::MAX);
+        assert_eq!(converter.convert::<i16>(-1.), i16::MIN);
+        assert_eq!(converter.convert::<i32>(1.), i32::MAX);
+        assert_eq!(converter.convert::<i32>(-1.), i32::MIN);
+        assert_eq!(converter.convert::<i16>(0.5), 16384);
+        assert_eq!(converter.convert::<i16>(0.), 0);
+    }
+
+    #[test]
+    fn unsigned_is_offset_binary() {
+        let mut converter = Converter::new(false);
+        assert_eq!(converter.convert::<u8>(0.), 0x80);
+        assert_eq!(converter.convert::<u8>(-1.), 0);
+        assert_eq!(converter.convert::<u8>(1.), u8::MAX);
+        assert_eq!(converter.convert::<u16>(0.), 0x8000);
+        assert_eq!(converter.convert::<u16>(-1.), 0);
+        assert_eq!(converter.convert::<u16>(1.), u16::MAX);
+        assert_eq!(converter.convert::<u32>(0.), 0x8000_0000);
+        assert_eq!(converter.convert::<u32>(-1.), 0);
+        assert_eq!(converter.convert::<u32>(1.), u32::MAX);
+    }
+
+    #[test]
+    fn out_of_range_does_not_wrap() {
+        let mut converter = Converter::new(false);
+        for sample in [1.5, 10., f32::MAX, f32::INFINITY] {
+            assert_eq!(converter.convert::<i8>(sample), i8::MAX);
+            assert_eq!(converter.convert::<i16>(sample), i16::MAX);
+            assert_eq!(converter.convert::<i32>(sample), i32::MAX);
+            assert_eq!(converter.convert::<u8>(sample), u8::MAX);
+            assert_eq!(converter.convert::<u16>(sample), u16::MAX);
+            assert_eq!(converter.convert::<u32>(sample), u32::MAX);
+            assert_eq!(converter.convert::<f32>(sample), 1.);
+            assert_eq!(converter.convert::<i16>(-sample), i16::MIN);
+            assert_eq!(converter.convert::<u16>(-sample), 0);
+            assert_eq!(converter.convert::<f32>(-sample), -1.);
+        }
+    }
+
+    #[test]
+    fn dither_stays_within_one_lsb() {
+        let mut plain = Converter::new(false);
+        let mut dithered = Converter::new(true);
+        for step in -1000..=1000 {
+            let sample = step as f32 / 1000.;
+            let expected = plain.convert::<i16>(sample) as i32;
+            for _ in 0..20 {
+                let value = dithered.convert::<i16>(sample) as i32;
+                assert!(
+                    (value - expected).abs() <= 1,
+                    "{sample}: {value} vs {expected}"
+                );
+            }
+        }
+    }
+}
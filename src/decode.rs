@@ -0,0 +1,368 @@
+use anyhow::{anyhow, Result};
+use dasp_interpolate::linear::Linear;
+use dasp_signal::Signal;
+use hound::{WavReader, WavSpec};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::cli::Encoding;
+use crate::raw::{self, RawSpec};
+use crate::wav::fit_streamed_sizes;
+
+/// File extensions `read_audio` can decode.
+pub const PLAYABLE: [&str; 2] = ["wav", "flac"];
+
+/// Reads a wav or flac file, or raw PCM if `raw` is given, into its sample
+/// rate and one `Vec` of samples in `-1.0..1.0` per channel. `-` reads stdin.
+pub fn read_audio(path: &Path, raw: Option<&RawSpec>) -> Result<(u32, Vec<Vec<f32>>)> {
+    let is_stdin = path == Path::new("-");
+    if let Some(raw) = raw {
+        let data = if is_stdin {
+            raw::decode(stdin_bytes()?, raw)
+        } else {
+            raw::decode(&std::fs::read(path)?, raw)
+        };
+        return Ok((raw.sample_rate, data));
+    }
+    if is_stdin {
+        let mut bytes = stdin_bytes()?.to_vec();
+        fit_streamed_sizes(&mut bytes);
+        let (spec, data) = decode_wav(WavReader::new(Cursor::new(bytes))?)?;
+        return Ok((spec.sample_rate, data));
+    }
+    match extension(path).as_deref() {
+        Some("flac") => read_flac(path),
+        _ => {
+            let (spec, data) = read_wav(path)?;
+            Ok((spec.sample_rate, data))
+        }
+    }
+}
+
+/// Everything on stdin, read once so `-` can be read more than once.
+pub fn stdin_bytes() -> Result<&'static [u8]> {
+    static STDIN: OnceLock<Vec<u8>> = OnceLock::new();
+    if let Some(bytes) = STDIN.get() {
+        return Ok(bytes);
+    }
+    let mut bytes = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut bytes)?;
+    Ok(STDIN.get_or_init(|| bytes))
+}
+
+/// The extension of `path` in lower case.
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase)
+}
+
+/// The format of an audio file, as its header gives it.
+pub struct Info {
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub bits_per_sample: u32,
+    pub frames: u64,
+}
+
+impl Info {
+    pub fn duration(&self) -> f64 {
+        self.frames as f64 / self.sample_rate as f64
+    }
+}
+
+/// Reads the format of a wav or flac file without decoding it.
+pub fn probe(path: &Path) -> Result<Info> {
+    match extension(path).as_deref() {
+        Some("flac") => {
+            let info = claxon::FlacReader::open(path)?.streaminfo();
+            Ok(Info {
+                sample_rate: info.sample_rate,
+                channels: info.channels,
+                bits_per_sample: info.bits_per_sample,
+                frames: info.samples.unwrap_or(0),
+            })
+        }
+        _ => {
+            let reader = WavReader::open(path)?;
+            let spec = reader.spec();
+            Ok(Info {
+                sample_rate: spec.sample_rate,
+                channels: spec.channels as u32,
+                bits_per_sample: spec.bits_per_sample as u32,
+                frames: reader.duration() as u64,
+            })
+        }
+    }
+}
+
+/// The kind of file a [`Reader`] decodes.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Container {
+    Wav(hound::SampleFormat),
+    Flac,
+    Raw(Encoding),
+}
+
+/// Where a [`Reader`] decodes from.
+enum Source {
+    Wav(WavReader<Box<dyn Read>>),
+    Flac(claxon::FlacReader<Box<dyn Read>>),
+    Raw(Box<dyn Read>, Encoding),
+}
+
+/// A wav, flac or raw file decoded a block at a time, so that files of any
+/// length can be processed without loading them whole. `-` reads stdin.
+pub struct Reader {
+    pub info: Info,
+    source: Source,
+    /// Decoded samples not returned yet, as flac decodes a frame at a time.
+    pending: Vec<f32>,
+    buffer: Vec<i32>,
+    bytes: Vec<u8>,
+}
+
+impl Reader {
+    /// Opens `path`, as raw PCM if `raw` is given.
+    pub fn open(path: &Path, raw: Option<&RawSpec>) -> Result<Reader> {
+        let is_stdin = path == Path::new("-");
+        let (info, source) = if let Some(raw) = raw {
+            let (input, len): (Box<dyn Read>, u64) = if is_stdin {
+                let bytes = stdin_bytes()?;
+                (Box::new(bytes), bytes.len() as u64)
+            } else {
+                let file = File::open(path)?;
+                let len = file.metadata()?.len();
+                (Box::new(BufReader::new(file)), len)
+            };
+            let width = raw.encoding.width() as u64;
+            let info = Info {
+                sample_rate: raw.sample_rate,
+                channels: raw.channels as u32,
+                bits_per_sample: width as u32 * 8,
+                frames: len / (width * raw.channels as u64),
+            };
+            (info, Source::Raw(input, raw.encoding))
+        } else {
+            let (input, is_flac): (Box<dyn Read>, bool) = if is_stdin {
+                let mut bytes = stdin_bytes()?.to_vec();
+                fit_streamed_sizes(&mut bytes);
+                let is_flac = bytes.starts_with(b"fLaC");
+                (Box::new(Cursor::new(bytes)), is_flac)
+            } else {
+                let file = BufReader::new(File::open(path)?);
+                (Box::new(file), extension(path).as_deref() == Some("flac"))
+            };
+            if is_flac {
+                let reader = claxon::FlacReader::new(input)?;
+                let streaminfo = reader.streaminfo();
+                let info = Info {
+                    sample_rate: streaminfo.sample_rate,
+                    channels: streaminfo.channels,
+                    bits_per_sample: streaminfo.bits_per_sample,
+                    frames: streaminfo.samples.unwrap_or(0),
+                };
+                (info, Source::Flac(reader))
+            } else {
+                let reader = WavReader::new(input)?;
+                let spec = reader.spec();
+                let info = Info {
+                    sample_rate: spec.sample_rate,
+                    channels: spec.channels as u32,
+                    bits_per_sample: spec.bits_per_sample as u32,
+                    frames: reader.duration() as u64,
+                };
+                (info, Source::Wav(reader))
+            }
+        };
+        if info.channels == 0 {
+            return Err(anyhow!("{} has no channels", path.display()));
+        }
+        Ok(Reader {
+            info,
+            source,
+            pending: Vec::new(),
+            buffer: Vec::new(),
+            bytes: Vec::new(),
+        })
+    }
+
+    pub fn container(&self) -> Container {
+        match &self.source {
+            Source::Wav(reader) => Container::Wav(reader.spec().sample_format),
+            Source::Flac(_) => Container::Flac,
+            Source::Raw(_, encoding) => Container::Raw(*encoding),
+        }
+    }
+
+    /// The Vorbis comments of a flac file, as `(name, value)` pairs.
+    pub fn comments(&self) -> Vec<(String, String)> {
+        match &self.source {
+            Source::Flac(reader) => reader
+                .tags()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Reads up to `frames` frames of interleaved samples in `-1.0..1.0` into
+    /// `out`, replacing what it held. Returns how many frames were read, which
+    /// is 0 at the end of the file.
+    pub fn read(&mut self, frames: usize, out: &mut Vec<f32>) -> Result<usize> {
+        let channels = self.info.channels as usize;
+        let wanted = frames * channels;
+        let scale = (1u64 << (self.info.bits_per_sample.max(1) - 1)) as f32;
+        out.clear();
+        match &mut self.source {
+            Source::Wav(reader) => match reader.spec().sample_format {
+                hound::SampleFormat::Float => {
+                    for sample in reader.samples::<f32>().take(wanted) {
+                        out.push(sample?);
+                    }
+                }
+                hound::SampleFormat::Int => {
+                    for sample in reader.samples::<i32>().take(wanted) {
+                        out.push(sample? as f32 / scale);
+                    }
+                }
+            },
+            Source::Flac(reader) => {
+                let mut blocks = reader.blocks();
+                while self.pending.len() < wanted {
+                    let buffer = std::mem::take(&mut self.buffer);
+                    let Some(block) = blocks.read_next_or_eof(buffer)? else {
+                        break;
+                    };
+                    for index in 0..block.duration() {
+                        for channel in 0..channels as u32 {
+                            self.pending
+                                .push(block.sample(channel, index) as f32 / scale);
+                        }
+                    }
+                    self.buffer = block.into_buffer();
+                }
+                let count = wanted.min(self.pending.len());
+                out.extend(self.pending.drain(..count));
+            }
+            Source::Raw(input, encoding) => {
+                let width = encoding.width();
+                self.bytes.resize(wanted * width, 0);
+                let mut filled = 0;
+                while filled < self.bytes.len() {
+                    match input.read(&mut self.bytes[filled..])? {
+                        0 => break,
+                        count => filled += count,
+                    }
+                }
+                out.extend(
+                    self.bytes[..filled]
+                        .chunks_exact(width)
+                        .map(|sample| encoding.decode(sample)),
+                );
+            }
+        }
+        // A trailing partial frame is dropped.
+        out.truncate(out.len() / channels * channels);
+        Ok(out.len() / channels)
+    }
+}
+
+/// Reads a flac file into its sample rate and one `Vec` of samples per channel.
+pub fn read_flac(path: &Path) -> Result<(u32, Vec<Vec<f32>>)> {
+    let mut reader = claxon::FlacReader::open(path)?;
+    let info = reader.streaminfo();
+    let num_channels = info.channels as usize;
+    let scale = (1u64 << (info.bits_per_sample - 1)) as f32;
+
+    let mut file_data = vec![Vec::new(); num_channels];
+    for (sample_count, result) in reader.samples().enumerate() {
+        file_data[sample_count % num_channels].push(result? as f32 / scale);
+    }
+    Ok((info.sample_rate, file_data))
+}
+
+/// Reads a wav file into one `Vec` of samples in `-1.0..1.0` per channel.
+pub fn read_wav(file_path: &Path) -> Result<(WavSpec, Vec<Vec<f32>>)> {
+    decode_wav(WavReader::open(file_path)?)
+}
+
+fn decode_wav<R: Read>(mut reader: WavReader<R>) -> Result<(WavSpec, Vec<Vec<f32>>)> {
+    let spec = reader.spec();
+
+    let num_channels = spec.channels as usize;
+    let mut file_data: Vec<Vec<f32>> = vec![];
+
+    for _ in 0..num_channels {
+        file_data.push(Vec::new());
+    }
+
+    let mut sample_count = 0;
+
+    match spec.sample_format {
+        hound::SampleFormat::Int => match spec.bits_per_sample {
+            16 => {
+                for result in reader.samples::<i16>() {
+                    let sample = result? as f32 / i16::MAX as f32;
+                    let channel = sample_count % num_channels;
+                    file_data[channel].push(sample);
+                    sample_count += 1;
+                }
+            }
+
+            24 => {
+                for result in reader.samples::<i32>() {
+                    let sample = result?;
+                    let sample = if sample & (1 << 23) != 0 {
+                        (sample | !0xff_ffff) as f32
+                    } else {
+                        sample as f32
+                    };
+                    let sample = sample / (1 << 23) as f32;
+                    let channel = sample_count % num_channels;
+                    file_data[channel].push(sample);
+                    sample_count += 1;
+                }
+            }
+
+            32 => {
+                for result in reader.samples::<i32>() {
+                    let sample = result? as f32 / i32::MAX as f32;
+                    let channel = sample_count % num_channels;
+                    file_data[channel].push(sample);
+                    sample_count += 1;
+                }
+            }
+            bits => return Err(anyhow!("unsupported bit depth {}", bits)),
+        },
+        hound::SampleFormat::Float => {
+            for result in reader.samples::<f32>() {
+                let sample = result?;
+                let channel = sample_count % num_channels;
+                file_data[channel].push(sample);
+                sample_count += 1;
+            }
+        }
+    }
+
+    Ok((spec, file_data))
+}
+
+/// Resamples each channel from `source_sr` to `target_sr` with linear interpolation.
+pub fn resample(channels: &[Vec<f32>], source_sr: f64, target_sr: f64) -> Vec<Vec<f32>> {
+    channels
+        .iter()
+        .map(|channel| {
+            let mut source = dasp_signal::from_iter(channel.iter().cloned());
+            let a = source.next();
+            let b = source.next();
+            let interp = Linear::new(a, b);
+            source
+                .from_hz_to_hz(interp, source_sr, target_sr)
+                .until_exhausted()
+                .collect()
+        })
+        .collect()
+}
@@ -0,0 +1,388 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use hound::{SampleFormat, WavSpec};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::analysis::{to_db, Loudness};
+use crate::cli::{EditArgs, Encoding, FadeArgs, FadeShape, GainArgs, NormalizeArgs, TrimArgs};
+use crate::convert::Quantizer;
+use crate::decode::{stdin_bytes, Container, Reader};
+use crate::flac::FlacWriter;
+use crate::raw::RawSpec;
+use crate::wav::{metadata_chunks, PcmWriter, WavFileWriter};
+use crate::writer::Encoder;
+
+/// Frames processed at a time.
+const CHUNK: usize = 65536;
+
+/// Where a `bext` chunk keeps its time reference, in samples since midnight.
+const BEXT_TIME_REFERENCE: usize = 338;
+
+/// Writes an edited file in the container its extension names, or else the
+/// input's, in the input's sample format where the container can hold it,
+/// keeping the input's metadata where the container has room for it.
+pub struct Output {
+    encoder: Box<dyn Encoder>,
+    float: bool,
+    quantizer: Quantizer,
+    ints: Vec<i32>,
+    clipped: u64,
+}
+
+impl Output {
+    /// Edits that change the samples get dither; cutting them is exact
+    /// without. `start` is the input frame the output begins at.
+    pub fn create(
+        path: &Path,
+        input: &Path,
+        reader: &Reader,
+        dither: bool,
+        start: u64,
+    ) -> Result<Self> {
+        let info = &reader.info;
+        let native = WavSpec {
+            channels: info.channels as u16,
+            sample_rate: info.sample_rate,
+            bits_per_sample: info.bits_per_sample as u16,
+            sample_format: SampleFormat::Int,
+        };
+        let spec = match reader.container() {
+            Container::Wav(sample_format) => WavSpec {
+                sample_format,
+                ..native
+            },
+            Container::Flac => native,
+            Container::Raw(encoding) => encoding.spec(&native),
+        };
+        let to_stdout = path == Path::new("-");
+        let container = output_container(path, reader.container(), &spec);
+        if container == Container::Flac && spec.sample_format == SampleFormat::Float {
+            return Err(anyhow!(
+                "flac can't hold float samples, write {} as wav instead",
+                path.display()
+            ));
+        }
+        let encoder: Box<dyn Encoder> = match container {
+            Container::Raw(encoding) => {
+                let sink: Box<dyn Write + Send> = if to_stdout {
+                    Box::new(std::io::stdout())
+                } else {
+                    Box::new(File::create(path)?)
+                };
+                Box::new(PcmWriter::new(sink, spec, Some(encoding))?)
+            }
+            _ if to_stdout => Box::new(PcmWriter::new(Box::new(std::io::stdout()), spec, None)?),
+            Container::Flac => Box::new(FlacWriter::create(
+                path,
+                spec.channels,
+                spec.sample_rate,
+                spec.bits_per_sample,
+                &reader.comments(),
+            )?),
+            Container::Wav(_) => {
+                let mut wav = WavFileWriter::create(path, spec)?;
+                let metadata = match reader.container() {
+                    Container::Wav(_) => wav_metadata(input)?,
+                    _ => Vec::new(),
+                };
+                for (id, mut body) in metadata {
+                    if &id == b"bext" && body.len() >= BEXT_TIME_REFERENCE + 8 {
+                        let field = &mut body[BEXT_TIME_REFERENCE..BEXT_TIME_REFERENCE + 8];
+                        let time = u64::from_le_bytes(field.try_into().unwrap());
+                        field.copy_from_slice(&time.wrapping_add(start).to_le_bytes());
+                    }
+                    wav.add_chunk(id, &body);
+                }
+                Box::new(wav)
+            }
+        };
+        Ok(Output {
+            encoder,
+            float: spec.sample_format == SampleFormat::Float,
+            quantizer: if dither {
+                Quantizer::new(spec.bits_per_sample)
+            } else {
+                Quantizer::exact(spec.bits_per_sample)
+            },
+            ints: Vec::new(),
+            clipped: 0,
+        })
+    }
+
+    pub fn write(&mut self, samples: &[f32]) -> Result<()> {
+        if self.float {
+            return self.encoder.write_float(samples);
+        }
+        self.clipped += samples.iter().filter(|sample| sample.abs() > 1.).count() as u64;
+        self.ints.clear();
+        self.quantizer.quantize_float(samples, &mut self.ints);
+        self.encoder.write_int(&self.ints)
+    }
+
+    /// Finishes the file, returning how many samples were clipped.
+    pub fn finish(self) -> Result<u64> {
+        self.encoder.finalize()?;
+        Ok(self.clipped)
+    }
+}
+
+/// The container the extension of `path` asks for, or `input`'s if it names
+/// none that can be written.
+fn output_container(path: &Path, input: Container, spec: &WavSpec) -> Container {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match (extension.as_deref(), input) {
+        (Some("wav"), Container::Wav(_)) | (Some("raw" | "pcm"), Container::Raw(_)) => input,
+        (Some("wav"), _) => Container::Wav(spec.sample_format),
+        (Some("flac"), _) => Container::Flac,
+        (Some("raw" | "pcm"), _) => Container::Raw(Encoding::native(spec)),
+        _ => input,
+    }
+}
+
+fn wav_metadata(input: &Path) -> Result<Vec<([u8; 4], Vec<u8>)>> {
+    Ok(if input == Path::new("-") {
+        metadata_chunks(&mut Cursor::new(stdin_bytes()?))
+    } else {
+        metadata_chunks(&mut BufReader::new(File::open(input)?))
+    })
+}
+
+/// What an edit wrote.
+struct Written {
+    frames: u64,
+    /// Samples beyond full scale, clamped as integer output can't hold them.
+    clipped: u64,
+}
+
+/// Refuses to overwrite the file being read.
+pub fn check_output(input: &Path, output: &Path) -> Result<()> {
+    if input != Path::new("-")
+        && output.exists()
+        && input.canonicalize()? == output.canonicalize()?
+    {
+        return Err(anyhow!("the result must go to another file than the input"));
+    }
+    Ok(())
+}
+
+fn open_input(args: &EditArgs) -> Result<Reader> {
+    let input = Path::new(&args.input);
+    check_output(input, Path::new(&args.output))?;
+    Reader::open(input, RawSpec::from_args(&args.raw).as_ref())
+}
+
+/// Streams the frames of `reader` in `range` to the output, passing every
+/// chunk through `process` along with the input frame it starts at.
+fn rewrite(
+    mut reader: Reader,
+    args: &EditArgs,
+    range: Range<u64>,
+    dither: bool,
+    mut process: impl FnMut(u64, &mut [f32]),
+) -> Result<Written> {
+    let channels = reader.info.channels as usize;
+    let mut output = Output::create(
+        Path::new(&args.output),
+        Path::new(&args.input),
+        &reader,
+        dither,
+        range.start,
+    )?;
+    let mut buffer = Vec::new();
+    let mut position = 0;
+    let mut frames = 0;
+    while position < range.end {
+        let read = reader.read(CHUNK, &mut buffer)? as u64;
+        if read == 0 {
+            break;
+        }
+        let first = range.start.saturating_sub(position).min(read);
+        let last = (range.end - position).min(read);
+        if first < last {
+            let chunk = &mut buffer[first as usize * channels..last as usize * channels];
+            process(position + first, chunk);
+            output.write(chunk)?;
+            frames += last - first;
+        }
+        position += read;
+    }
+    Ok(Written {
+        frames,
+        clipped: output.finish()?,
+    })
+}
+
+/// Frames in `reader`, counted by decoding it if the header doesn't say.
+fn total_frames(reader: &Reader, args: &EditArgs) -> Result<u64> {
+    if reader.info.frames > 0 {
+        return Ok(reader.info.frames);
+    }
+    let mut reader = open_input(args)?;
+    let mut buffer = Vec::new();
+    let mut frames = 0;
+    loop {
+        match reader.read(CHUNK, &mut buffer)? {
+            0 => return Ok(frames),
+            read => frames += read as u64,
+        }
+    }
+}
+
+fn to_frames(time: Duration, sample_rate: u32) -> u64 {
+    (time.as_secs_f64() * sample_rate as f64).round() as u64
+}
+
+/// Prints what was done, on stderr if the result went to stdout.
+fn report(done: &str, args: &EditArgs, written: &Written, sample_rate: u32) {
+    let line = |line: String| {
+        if args.output == "-" {
+            eprintln!("{}", line);
+        } else {
+            println!("{}", line);
+        }
+    };
+    line(format!(
+        "{} {} -> {}: {} frames ({:.2}s)",
+        done.green().bold(),
+        args.input,
+        args.output.cyan(),
+        written.frames,
+        written.frames as f64 / sample_rate as f64
+    ));
+    if written.clipped > 0 {
+        line(
+            format!(
+                "{} samples beyond full scale were clipped.",
+                written.clipped
+            )
+            .yellow()
+            .to_string(),
+        );
+    }
+}
+
+pub fn trim(args: &TrimArgs) -> Result<()> {
+    let reader = open_input(&args.edit)?;
+    let sample_rate = reader.info.sample_rate;
+    let start = args.start.map_or(0, |start| to_frames(start, sample_rate));
+    let end = args.end.map_or(u64::MAX, |end| to_frames(end, sample_rate));
+    if end <= start {
+        return Err(anyhow!("--end must come after --start"));
+    }
+    let written = rewrite(reader, &args.edit, start..end, false, |_, _| {})?;
+    report("Trimmed", &args.edit, &written, sample_rate);
+    Ok(())
+}
+
+/// The gain `t` of the way through a fade in, from 0 to 1. Fades out run
+/// the same curve backwards.
+fn fade_gain(shape: FadeShape, t: f64) -> f64 {
+    match shape {
+        FadeShape::Lin => t,
+        // From -60 dB, offset so the fade still starts from silence.
+        FadeShape::Log => (10f64.powf(3. * (t - 1.)) - 0.001) / 0.999,
+        FadeShape::Scurve => (1. - (std::f64::consts::PI * t).cos()) / 2.,
+    }
+}
+
+pub fn fade(args: &FadeArgs) -> Result<()> {
+    let reader = open_input(&args.edit)?;
+    let sample_rate = reader.info.sample_rate;
+    let channels = reader.info.channels as usize;
+    let total = total_frames(&reader, &args.edit)?;
+    let fade_in = args.fade_in.map_or(0, |time| to_frames(time, sample_rate));
+    let fade_out = args.fade_out.map_or(0, |time| to_frames(time, sample_rate));
+    let written = rewrite(reader, &args.edit, 0..u64::MAX, true, |first, chunk| {
+        for (index, frame) in chunk.chunks_exact_mut(channels).enumerate() {
+            let position = first + index as u64;
+            let mut gain = 1.;
+            if position < fade_in {
+                gain *= fade_gain(args.shape, position as f64 / fade_in as f64);
+            }
+            let remaining = total.saturating_sub(position + 1);
+            if remaining < fade_out {
+                gain *= fade_gain(args.shape, remaining as f64 / fade_out as f64);
+            }
+            for sample in frame {
+                *sample = (*sample as f64 * gain) as f32;
+            }
+        }
+    })?;
+    report("Faded", &args.edit, &written, sample_rate);
+    Ok(())
+}
+
+/// Rewrites the input `db` louder.
+fn apply_gain(reader: Reader, args: &EditArgs, db: f64) -> Result<Written> {
+    let factor = 10f64.powf(db / 20.);
+    rewrite(reader, args, 0..u64::MAX, true, |_, chunk| {
+        for sample in chunk {
+            *sample = (*sample as f64 * factor) as f32;
+        }
+    })
+}
+
+pub fn gain(args: &GainArgs) -> Result<()> {
+    let reader = open_input(&args.edit)?;
+    let sample_rate = reader.info.sample_rate;
+    let written = apply_gain(reader, &args.edit, args.gain)?;
+    report(
+        &format!("Gain {:+.1} dB", args.gain),
+        &args.edit,
+        &written,
+        sample_rate,
+    );
+    Ok(())
+}
+
+pub fn normalize(args: &NormalizeArgs) -> Result<()> {
+    // A first pass measures, a second applies the gain.
+    let mut reader = open_input(&args.edit)?;
+    let sample_rate = reader.info.sample_rate;
+    let channels = reader.info.channels as usize;
+    let mut peak = 0f32;
+    let mut loudness = Loudness::new(channels, sample_rate);
+    let mut buffer = Vec::new();
+    while reader.read(CHUNK, &mut buffer)? > 0 {
+        if args.lufs.is_some() {
+            for frame in buffer.chunks_exact(channels) {
+                loudness.add_frame(frame);
+            }
+        } else {
+            peak = buffer
+                .iter()
+                .fold(peak, |peak, sample| peak.max(sample.abs()));
+        }
+    }
+    let (measured, target, unit) = match (args.peak, args.lufs) {
+        (Some(target), _) => (to_db(peak as f64), target, "dBFS"),
+        (_, Some(target)) => (loudness.integrated(), target, "LUFS"),
+        _ => unreachable!("clap requires --peak or --lufs"),
+    };
+    if !measured.is_finite() {
+        return Err(anyhow!(
+            "{} is silent, there is nothing to normalize",
+            args.edit.input
+        ));
+    }
+
+    let db = target - measured;
+    let written = apply_gain(open_input(&args.edit)?, &args.edit, db)?;
+    report(
+        &format!(
+            "Normalized {:.1} {} to {:.1} {} ({:+.1} dB)",
+            measured, unit, target, unit, db
+        ),
+        &args.edit,
+        &written,
+        sample_rate,
+    );
+    Ok(())
+}
@@ -0,0 +1,593 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Number of frames per FLAC block. 4096 is the reference encoder's default
+/// for 44.1/48 kHz material.
+const BLOCK_SIZE: usize = 4096;
+const MAX_FIXED_ORDER: usize = 4;
+const MAX_PARTITION_ORDER: usize = 8;
+/// Offset of the STREAMINFO body: `fLaC` plus the metadata block header.
+const STREAMINFO_OFFSET: u64 = 8;
+
+/// A streaming FLAC encoder using fixed linear predictors, Rice coded
+/// residuals and stereo decorrelation.
+///
+/// It is far simpler than libFLAC (no LPC, no MD5 signature), but fast enough
+/// to run in the recording writer thread and gets most of the way to the
+/// reference encoder's compression on typical recordings.
+pub struct FlacWriter {
+    writer: BufWriter<File>,
+    channels: usize,
+    sample_rate: u32,
+    bits_per_sample: u32,
+    pending: Vec<Vec<i32>>,
+    next_channel: usize,
+    bytes_written: u64,
+    total_frames: u64,
+    frame_number: u64,
+    min_frame_size: u32,
+    max_frame_size: u32,
+}
+
+impl FlacWriter {
+    /// Starts a FLAC file, tagged with `comments` as `(name, value)` pairs.
+    pub fn create(
+        path: &Path,
+        channels: u16,
+        sample_rate: u32,
+        bits_per_sample: u16,
+        comments: &[(String, String)],
+    ) -> anyhow::Result<Self> {
+        if !(1..=8).contains(&channels) {
+            return Err(anyhow::anyhow!(
+                "flac supports 1 to 8 channels, not {channels}"
+            ));
+        }
+        if !matches!(bits_per_sample, 8 | 16 | 24) {
+            return Err(anyhow::anyhow!(
+                "{bits_per_sample}-bit flac is not supported"
+            ));
+        }
+
+        let mut flac = FlacWriter {
+            writer: BufWriter::new(File::create(path)?),
+            channels: channels as usize,
+            sample_rate,
+            bits_per_sample: bits_per_sample as u32,
+            pending: vec![Vec::with_capacity(BLOCK_SIZE); channels as usize],
+            next_channel: 0,
+            bytes_written: 0,
+            total_frames: 0,
+            frame_number: 0,
+            min_frame_size: 0,
+            max_frame_size: 0,
+        };
+        flac.writer.write_all(b"fLaC")?;
+        // Metadata block type STREAMINFO, 34 bytes long, the last one unless
+        // comments follow.
+        let last = if comments.is_empty() { 0x80 } else { 0 };
+        flac.writer.write_all(&[last, 0, 0, 34])?;
+        let streaminfo = flac.streaminfo();
+        flac.writer.write_all(&streaminfo)?;
+        flac.bytes_written = STREAMINFO_OFFSET + streaminfo.len() as u64;
+        if !comments.is_empty() {
+            let block = vorbis_comment(comments);
+            let len = (block.len() as u32).to_be_bytes();
+            // Last metadata block, type VORBIS_COMMENT.
+            flac.writer.write_all(&[0x84, len[1], len[2], len[3]])?;
+            flac.writer.write_all(&block)?;
+            flac.bytes_written += 4 + block.len() as u64;
+        }
+        Ok(flac)
+    }
+
+    /// Upper bound of the encoded size of one frame, used to keep split files
+    /// under their size limit while samples are still buffered.
+    pub fn frame_bytes(&self) -> u64 {
+        // A verbatim subframe of a side channel needs one extra bit; the
+        // frame headers are well covered by the extra byte.
+        (self.channels as u64 * (self.bits_per_sample as u64 + 1)).div_ceil(8) + 1
+    }
+
+    /// Bytes on disk plus a worst-case estimate for samples not yet encoded.
+    pub fn len(&self) -> u64 {
+        self.bytes_written + self.pending[0].len() as u64 * self.frame_bytes()
+    }
+
+    /// Writes interleaved samples, already quantized to `bits_per_sample`.
+    pub fn write_samples(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        for &sample in samples {
+            self.pending[self.next_channel].push(sample);
+            self.next_channel += 1;
+            if self.next_channel == self.channels {
+                self.next_channel = 0;
+                if self.pending[0].len() == BLOCK_SIZE {
+                    self.write_frame()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Rewrites STREAMINFO with the number of samples encoded so far and
+    /// flushes to disk.
+    pub fn update_header(&mut self) -> anyhow::Result<()> {
+        let streaminfo = self.streaminfo();
+        self.writer.seek(SeekFrom::Start(STREAMINFO_OFFSET))?;
+        self.writer.write_all(&streaminfo)?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> anyhow::Result<()> {
+        // A trailing partial frame cannot be encoded, drop it.
+        let frames = self.pending.iter().map(Vec::len).min().unwrap_or(0);
+        for channel in self.pending.iter_mut() {
+            channel.truncate(frames);
+        }
+        if frames > 0 {
+            self.write_frame()?;
+        }
+        self.update_header()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(())
+    }
+
+    fn streaminfo(&self) -> [u8; 34] {
+        let mut bits = BitWriter::default();
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(BLOCK_SIZE as u64, 16);
+        bits.write(self.min_frame_size as u64, 24);
+        bits.write(self.max_frame_size as u64, 24);
+        bits.write(self.sample_rate as u64, 20);
+        bits.write(self.channels as u64 - 1, 3);
+        bits.write(self.bits_per_sample as u64 - 1, 5);
+        bits.write(self.total_frames >> 32, 4);
+        bits.write(self.total_frames & 0xffff_ffff, 32);
+        // An all-zero MD5 signature means "unknown".
+        let mut streaminfo = [0u8; 34];
+        streaminfo[..18].copy_from_slice(&bits.bytes);
+        streaminfo
+    }
+
+    fn write_frame(&mut self) -> anyhow::Result<()> {
+        let block_size = self.pending[0].len();
+        let bps = self.bits_per_sample;
+        let mut frame = BitWriter::default();
+
+        frame.write(0xfff8, 16);
+        frame.write(
+            if block_size == BLOCK_SIZE {
+                0b1100
+            } else {
+                0b0111
+            },
+            4,
+        );
+        frame.write(0, 4);
+
+        let (assignment, subframes) = if self.channels == 2 {
+            let (left, right) = (&self.pending[0], &self.pending[1]);
+            let side: Vec<i32> = left.iter().zip(right).map(|(&l, &r)| l - r).collect();
+            let mid: Vec<i32> = left
+                .iter()
+                .zip(right)
+                .map(|(&l, &r)| (l + r) >> 1)
+                .collect();
+            let l = Subframe::plan(left, bps);
+            let r = Subframe::plan(right, bps);
+            let m = Subframe::plan(&mid, bps);
+            let s = Subframe::plan(&side, bps + 1);
+            let (assignment, _) = [
+                (1, l.bits + r.bits),
+                (8, l.bits + s.bits),
+                (9, s.bits + r.bits),
+                (10, m.bits + s.bits),
+            ]
+            .into_iter()
+            .min_by_key(|(_, bits)| *bits)
+            .unwrap();
+            let subframes = match assignment {
+                1 => vec![(l, left.clone()), (r, right.clone())],
+                8 => vec![(l, left.clone()), (s, side)],
+                9 => vec![(s, side), (r, right.clone())],
+                _ => vec![(m, mid), (s, side)],
+            };
+            (assignment, subframes)
+        } else {
+            let subframes = self
+                .pending
+                .iter()
+                .map(|samples| (Subframe::plan(samples, bps), samples.clone()))
+                .collect();
+            (self.channels as u64 - 1, subframes)
+        };
+
+        frame.write(assignment, 4);
+        frame.write(
+            match bps {
+                8 => 0b001,
+                16 => 0b100,
+                _ => 0b110,
+            },
+            3,
+        );
+        frame.write(0, 1);
+        frame.write_utf8(self.frame_number);
+        if block_size != BLOCK_SIZE {
+            frame.write(block_size as u64 - 1, 16);
+        }
+        let crc = crc8(&frame.bytes);
+        frame.write(crc as u64, 8);
+
+        for (index, (plan, samples)) in subframes.iter().enumerate() {
+            // The side channel is always the one carrying an extra bit.
+            let side = matches!((assignment, index), (8, 1) | (9, 0) | (10, 1));
+            plan.write(&mut frame, samples, bps + side as u32);
+        }
+        frame.align();
+        let crc = crc16(&frame.bytes);
+        frame.write(crc as u64, 16);
+
+        self.writer.write_all(&frame.bytes)?;
+        let frame_size = frame.bytes.len() as u32;
+        self.min_frame_size = match self.min_frame_size {
+            0 => frame_size,
+            min => min.min(frame_size),
+        };
+        self.max_frame_size = self.max_frame_size.max(frame_size);
+        self.bytes_written += frame_size as u64;
+        self.total_frames += block_size as u64;
+        self.frame_number += 1;
+        for channel in self.pending.iter_mut() {
+            channel.clear();
+        }
+        Ok(())
+    }
+}
+
+/// The body of a VORBIS_COMMENT metadata block. Unlike the rest of FLAC, its
+/// lengths are little-endian.
+fn vorbis_comment(comments: &[(String, String)]) -> Vec<u8> {
+    let vendor = concat!("asak ", env!("CARGO_PKG_VERSION"));
+    let mut block = Vec::new();
+    block.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    block.extend_from_slice(vendor.as_bytes());
+    block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for (name, value) in comments {
+        let comment = format!("{}={}", name, value);
+        block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        block.extend_from_slice(comment.as_bytes());
+    }
+    block
+}
+
+/// How one channel of a block is going to be encoded, and what it costs.
+#[derive(Clone, Copy)]
+struct Subframe {
+    kind: SubframeKind,
+    bits: u64,
+}
+
+#[derive(Clone, Copy)]
+enum SubframeKind {
+    Constant,
+    Verbatim,
+    Fixed {
+        order: usize,
+        partition_order: usize,
+    },
+}
+
+impl Subframe {
+    fn plan(samples: &[i32], bps: u32) -> Subframe {
+        let n = samples.len();
+        if samples.iter().all(|&s| s == samples[0]) {
+            return Subframe {
+                kind: SubframeKind::Constant,
+                bits: 8 + bps as u64,
+            };
+        }
+
+        let mut best = Subframe {
+            kind: SubframeKind::Verbatim,
+            bits: 8 + n as u64 * bps as u64,
+        };
+        for order in 0..=MAX_FIXED_ORDER.min(n - 1) {
+            let residual = fixed_residual(samples, order);
+            for partition_order in 0..=MAX_PARTITION_ORDER {
+                let partitions = 1 << partition_order;
+                if !n.is_multiple_of(partitions) || n / partitions <= order {
+                    break;
+                }
+                let bits = 8
+                    + (order as u64 * bps as u64)
+                    + 6
+                    + partitions as u64 * 5
+                    + partition_costs(&residual, n, order, partition_order)
+                        .iter()
+                        .map(|(_, bits)| bits)
+                        .sum::<u64>();
+                if bits < best.bits {
+                    best = Subframe {
+                        kind: SubframeKind::Fixed {
+                            order,
+                            partition_order,
+                        },
+                        bits,
+                    };
+                }
+            }
+        }
+        best
+    }
+
+    fn write(&self, bits: &mut BitWriter, samples: &[i32], bps: u32) {
+        match self.kind {
+            SubframeKind::Constant => {
+                bits.write(0, 8);
+                bits.write_signed(samples[0] as i64, bps);
+            }
+            SubframeKind::Verbatim => {
+                bits.write(0b0000_0010, 8);
+                for &sample in samples {
+                    bits.write_signed(sample as i64, bps);
+                }
+            }
+            SubframeKind::Fixed {
+                order,
+                partition_order,
+            } => {
+                bits.write((0b1000 | order as u64) << 1, 8);
+                for &sample in &samples[..order] {
+                    bits.write_signed(sample as i64, bps);
+                }
+                let residual = fixed_residual(samples, order);
+                // Residual coding method RICE2, with 5-bit parameters.
+                bits.write(0b01, 2);
+                bits.write(partition_order as u64, 4);
+                let costs = partition_costs(&residual, samples.len(), order, partition_order);
+                let mut start = 0;
+                for (index, (k, _)) in costs.iter().enumerate() {
+                    let len = partition_len(samples.len(), order, partition_order, index);
+                    bits.write(*k as u64, 5);
+                    for &r in &residual[start..start + len] {
+                        bits.write_rice(r, *k);
+                    }
+                    start += len;
+                }
+            }
+        }
+    }
+}
+
+/// Residual of the fixed polynomial predictor of the given order, excluding
+/// the `order` warm-up samples.
+fn fixed_residual(samples: &[i32], order: usize) -> Vec<i64> {
+    let s = |i: usize| samples[i] as i64;
+    (order..samples.len())
+        .map(|i| match order {
+            0 => s(i),
+            1 => s(i) - s(i - 1),
+            2 => s(i) - 2 * s(i - 1) + s(i - 2),
+            3 => s(i) - 3 * s(i - 1) + 3 * s(i - 2) - s(i - 3),
+            _ => s(i) - 4 * s(i - 1) + 6 * s(i - 2) - 4 * s(i - 3) + s(i - 4),
+        })
+        .collect()
+}
+
+fn partition_len(n: usize, order: usize, partition_order: usize, index: usize) -> usize {
+    let len = n >> partition_order;
+    if index == 0 {
+        len - order
+    } else {
+        len
+    }
+}
+
+/// Best Rice parameter and its estimated cost in bits for every partition.
+fn partition_costs(
+    residual: &[i64],
+    n: usize,
+    order: usize,
+    partition_order: usize,
+) -> Vec<(u32, u64)> {
+    let mut start = 0;
+    (0..1 << partition_order)
+        .map(|index| {
+            let len = partition_len(n, order, partition_order, index);
+            let sum: u64 = residual[start..start + len]
+                .iter()
+                .map(|&r| zigzag(r))
+                .sum();
+            start += len;
+            (0..31)
+                .map(|k| (k, len as u64 * (k as u64 + 1) + (sum >> k)))
+                .min_by_key(|(_, bits)| *bits)
+                .unwrap()
+        })
+        .collect()
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u64, bits: u32) {
+        debug_assert!(bits <= 32);
+        self.acc = (self.acc << bits) | (value & ((1u64 << bits) - 1));
+        self.bits += bits;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+        self.acc &= (1u64 << self.bits) - 1;
+    }
+
+    fn write_signed(&mut self, value: i64, bits: u32) {
+        self.write(value as u64, bits);
+    }
+
+    fn write_rice(&mut self, value: i64, k: u32) {
+        let folded = zigzag(value);
+        let mut quotient = folded >> k;
+        while quotient >= 32 {
+            self.write(0, 32);
+            quotient -= 32;
+        }
+        self.write(1, quotient as u32 + 1);
+        if k > 0 {
+            self.write(folded, k);
+        }
+    }
+
+    /// The "UTF-8" style variable length integer used for frame numbers.
+    fn write_utf8(&mut self, value: u64) {
+        if value < 0x80 {
+            self.write(value, 8);
+            return;
+        }
+        let extra = match value {
+            0..=0x7ff => 1,
+            0x800..=0xffff => 2,
+            0x1_0000..=0x1f_ffff => 3,
+            0x20_0000..=0x3ff_ffff => 4,
+            0x400_0000..=0x7fff_ffff => 5,
+            _ => 6,
+        };
+        let prefix = (0xff00u64 >> (extra + 1)) & 0xff;
+        self.write(prefix | (value >> (6 * extra)), 8);
+        for i in (0..extra).rev() {
+            self.write(0x80 | ((value >> (6 * i)) & 0x3f), 8);
+        }
+    }
+
+    fn align(&mut self) {
+        if self.bits > 0 {
+            self.write(0, 8 - self.bits);
+        }
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Encodes interleaved `samples` and decodes them again with claxon.
+    fn round_trip(
+        name: &str,
+        channels: u16,
+        bits_per_sample: u16,
+        samples: &[i32],
+    ) -> (claxon::metadata::StreamInfo, Vec<i32>) {
+        let path = std::env::temp_dir().join(format!("asak-{}-{}.flac", name, std::process::id()));
+        let mut flac = FlacWriter::create(&path, channels, 44100, bits_per_sample, &[]).unwrap();
+        flac.write_samples(samples).unwrap();
+        flac.finalize().unwrap();
+        let mut reader = claxon::FlacReader::open(&path).unwrap();
+        let streaminfo = reader.streaminfo();
+        let decoded = reader.samples().map(Result::unwrap).collect();
+        std::fs::remove_file(&path).unwrap();
+        (streaminfo, decoded)
+    }
+
+    fn assert_round_trip(name: &str, channels: u16, bits_per_sample: u16, samples: &[i32]) {
+        let (streaminfo, decoded) = round_trip(name, channels, bits_per_sample, samples);
+        assert_eq!(streaminfo.channels, channels as u32);
+        assert_eq!(streaminfo.bits_per_sample, bits_per_sample as u32);
+        assert_eq!(streaminfo.sample_rate, 44100);
+        assert_eq!(
+            streaminfo.samples,
+            Some((samples.len() / channels as usize) as u64)
+        );
+        assert!(decoded == samples, "{name} did not decode bit-exact");
+    }
+
+    /// Stereo frames of `left` and `right` at every index below `frames`.
+    fn stereo(
+        frames: usize,
+        left: impl Fn(usize) -> i32,
+        right: impl Fn(usize) -> i32,
+    ) -> Vec<i32> {
+        (0..frames).flat_map(|i| [left(i), right(i)]).collect()
+    }
+
+    #[test]
+    fn silence() {
+        assert_round_trip("silence", 2, 16, &vec![0; 2 * BLOCK_SIZE * 2]);
+    }
+
+    #[test]
+    fn full_scale_squares() {
+        for bits in [8, 16, 24] {
+            let max = (1 << (bits - 1)) - 1;
+            let min = -(1 << (bits - 1));
+            let square = |i: usize| if (i / 50).is_multiple_of(2) { max } else { min };
+            // Opposite channels put the side channel at its widest.
+            let samples = stereo(2 * BLOCK_SIZE, square, |i| square(i + 50));
+            assert_round_trip(&format!("square-{bits}"), 2, bits as u16, &samples);
+        }
+    }
+
+    #[test]
+    fn sines() {
+        for bits in [16, 24] {
+            let amplitude = ((1 << (bits - 1)) - 1) as f64;
+            let sine = |i: usize, hz: f64| {
+                (amplitude * (i as f64 * hz * std::f64::consts::TAU / 44100.).sin()).round() as i32
+            };
+            let samples = stereo(3 * BLOCK_SIZE, |i| sine(i, 440.), |i| sine(i, 1000.));
+            assert_round_trip(&format!("sine-{bits}"), 2, bits as u16, &samples);
+        }
+    }
+
+    #[test]
+    fn partial_last_block() {
+        let samples: Vec<i32> = (0..2 * BLOCK_SIZE + 100)
+            .map(|i| ((i * 7919) % 30000) as i32 - 15000)
+            .collect();
+        assert_round_trip("partial", 1, 16, &samples);
+        let multichannel: Vec<i32> = (0..(BLOCK_SIZE + 1) * 6)
+            .map(|i| ((i * 104729) % 60000) as i32 - 30000)
+            .collect();
+        assert_round_trip("partial-6ch", 6, 16, &multichannel);
+    }
+}
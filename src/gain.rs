@@ -0,0 +1,92 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+/// The quietest and loudest volume, in dB.
+pub const MIN_DB: f32 = -60.;
+pub const MAX_DB: f32 = 12.;
+
+/// Volume, mute and per-channel mute and solo, shared between the TUI and the
+/// output callback.
+pub struct Gains {
+    /// The volume in dB and as a factor, both `f32` bits.
+    db: AtomicU32,
+    linear: AtomicU32,
+    muted: AtomicBool,
+    channel_muted: Vec<AtomicBool>,
+    soloed: Vec<AtomicBool>,
+    solo_count: AtomicUsize,
+}
+
+impl Gains {
+    pub fn new(channels: usize, db: f32) -> Self {
+        let gains = Gains {
+            db: AtomicU32::new(0),
+            linear: AtomicU32::new(0),
+            muted: AtomicBool::new(false),
+            channel_muted: (0..channels).map(|_| AtomicBool::new(false)).collect(),
+            soloed: (0..channels).map(|_| AtomicBool::new(false)).collect(),
+            solo_count: AtomicUsize::new(0),
+        };
+        gains.set_db(db);
+        gains
+    }
+
+    pub fn db(&self) -> f32 {
+        f32::from_bits(self.db.load(Ordering::Relaxed))
+    }
+
+    pub fn set_db(&self, db: f32) {
+        let db = db.clamp(MIN_DB, MAX_DB);
+        self.db.store(db.to_bits(), Ordering::Relaxed);
+        let linear = 10f32.powf(db / 20.);
+        self.linear.store(linear.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.muted.load(Ordering::Relaxed)
+    }
+
+    pub fn toggle_mute(&self) {
+        self.muted.fetch_xor(true, Ordering::Relaxed);
+    }
+
+    pub fn is_channel_muted(&self, channel: usize) -> bool {
+        self.channel_muted
+            .get(channel)
+            .is_some_and(|muted| muted.load(Ordering::Relaxed))
+    }
+
+    pub fn toggle_channel_mute(&self, channel: usize) {
+        if let Some(muted) = self.channel_muted.get(channel) {
+            muted.fetch_xor(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_soloed(&self, channel: usize) -> bool {
+        self.soloed
+            .get(channel)
+            .is_some_and(|soloed| soloed.load(Ordering::Relaxed))
+    }
+
+    /// While any channel is soloed, only soloed channels are heard.
+    pub fn toggle_solo(&self, channel: usize) {
+        if let Some(soloed) = self.soloed.get(channel) {
+            if soloed.fetch_xor(true, Ordering::Relaxed) {
+                self.solo_count.fetch_sub(1, Ordering::Relaxed);
+            } else {
+                self.solo_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The factor `channel` should be played at right now.
+    pub fn target(&self, channel: usize) -> f32 {
+        let silenced = self.is_muted()
+            || self.is_channel_muted(channel)
+            || (self.solo_count.load(Ordering::Relaxed) > 0 && !self.is_soloed(channel));
+        if silenced {
+            0.
+        } else {
+            f32::from_bits(self.linear.load(Ordering::Relaxed))
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use crossbeam::channel::Sender;
+
+use crate::analysis::level;
+
+/// Frames reduced into one `Level` for the TUI.
+pub const BLOCK: usize = 128;
+
+/// Minimum, maximum and RMS of one channel over a block of frames.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Level {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Level {
+    pub fn peak(&self) -> f32 {
+        self.max.max(-self.min)
+    }
+
+    /// The level over all of `levels`, each covering the same number of frames.
+    pub fn combine<'a>(levels: impl IntoIterator<Item = &'a Level>) -> Level {
+        let mut combined = Level::default();
+        let mut square_sum = 0.;
+        let mut count = 0;
+        for level in levels {
+            combined.min = combined.min.min(level.min);
+            combined.max = combined.max.max(level.max);
+            square_sum += level.rms * level.rms;
+            count += 1;
+        }
+        combined.rms = (square_sum / count.max(1) as f32).sqrt();
+        combined
+    }
+
+    /// The level of every `BLOCK` samples of one channel.
+    pub fn blocks(channel: &[f32]) -> Vec<Level> {
+        channel
+            .chunks(BLOCK)
+            .map(|block| level(block.iter().copied()))
+            .collect()
+    }
+
+    /// Combines every `per_column` levels into one.
+    pub fn columns(levels: &[Level], per_column: usize) -> Vec<Level> {
+        levels
+            .chunks(per_column.max(1))
+            .map(Level::combine)
+            .collect()
+    }
+}
+
+/// Reduces interleaved audio to one `Level` per channel per `BLOCK` frames and
+/// sends them in channel order.
+///
+/// The TUI only draws levels, so it never needs the samples themselves. A block
+/// is sent whole or, if the TUI falls behind, not at all; nothing is allocated.
+pub struct Decimator {
+    channels: usize,
+    channel: usize,
+    frames: usize,
+    levels: Vec<Level>,
+    square_sums: Vec<f32>,
+    tx: Sender<Level>,
+}
+
+impl Decimator {
+    pub fn new(channels: usize, tx: Sender<Level>) -> Self {
+        Decimator {
+            channels,
+            channel: 0,
+            frames: 0,
+            levels: vec![Level::default(); channels],
+            square_sums: vec![0.; channels],
+            tx,
+        }
+    }
+
+    pub fn push(&mut self, samples: impl Iterator<Item = f32>) {
+        for sample in samples {
+            let level = &mut self.levels[self.channel];
+            level.min = level.min.min(sample);
+            level.max = level.max.max(sample);
+            self.square_sums[self.channel] += sample * sample;
+
+            self.channel += 1;
+            if self.channel == self.channels {
+                self.channel = 0;
+                self.frames += 1;
+                if self.frames == BLOCK {
+                    self.send();
+                }
+            }
+        }
+    }
+
+    fn send(&mut self) {
+        // We are the only sender, so the room can't shrink before we're done.
+        let room = self.tx.capacity().unwrap_or(usize::MAX) - self.tx.len();
+        for (level, square_sum) in self.levels.iter_mut().zip(&mut self.square_sums) {
+            level.rms = (*square_sum / self.frames as f32).sqrt();
+            if room >= self.channels {
+                self.tx.try_send(*level).ok();
+            }
+            *level = Level::default();
+            *square_sum = 0.;
+        }
+        self.frames = 0;
+    }
+}
@@ -3,7 +3,7 @@ use colored::*;
 
 mod record;
 use cpal::traits::{DeviceTrait, HostTrait};
-use inquire::{InquireError, Select, Text};
+use inquire::Text;
 use record::record_audio;
 
 mod playback;
@@ -12,167 +12,177 @@ use playback::play_audio;
 mod monitor;
 use monitor::start_monitoring;
 
+mod overdub;
+use overdub::overdub;
+
 mod cli;
 use cli::{Cli, Commands};
 
+use status::Status;
+use std::path::Path;
+use std::sync::Arc;
+
+mod align;
+mod analysis;
+mod browser;
+mod caf;
+mod convert;
+mod decode;
+mod edit;
+mod flac;
+mod gain;
+mod levels;
+mod overview;
+mod playlist;
+mod queue;
+mod raw;
+mod routing;
+mod split;
+mod stats;
+mod status;
+mod stretch;
+mod summary;
+mod transport;
+mod wav;
+mod writer;
+
 fn main() {
     let cli = Cli::parse();
 
-    match &cli.command {
-        Commands::Rec(args) => {
-            // Pass the respective JACK usage flag to play_audio based on compile-time detection
-            #[cfg(all(
-                any(
-                    target_os = "linux",
-                    target_os = "dragonfly",
-                    target_os = "freebsd",
-                    target_os = "netbsd"
-                ),
-                feature = "jack"
-            ))]
-            {
-                // If we're on the right platform and JACK is enabled, pass true to use JACK for playback
+    // Use the JACK host only if we're on the right platform and JACK is enabled
+    #[cfg(all(
+        any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        ),
+        feature = "jack"
+    ))]
+    let jack = cli.jack;
+    #[cfg(not(all(
+        any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        ),
+        feature = "jack"
+    )))]
+    let jack = false;
 
-                match &args.output {
-                    Some(output) => {
-                        record_audio(output.clone(), args.device, false).unwrap();
-                    }
-                    None => {
-                        let now = chrono::Utc::now();
-                        let name = format!(
-                            "{}.wav",
-                            now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-                        );
-                        // let output = Text::new("What is your name?").placeholder(name).prompt();
-                        let output = Text {
-                            initial_value: Some(&name),
-                            ..Text::new("Please enter the output wav file name:")
-                        }
-                        .prompt();
-                        match output {
-                            Ok(output) => record_audio(output, args.device, cli.jack).unwrap(),
-                            Err(_) => println!("Recording cancelled."),
-                        }
-                    }
-                };
+    let status = || Arc::new(Status::new(cli.log.as_deref()).unwrap());
+
+    match &cli.command {
+        Commands::Rec(args) => match &args.output {
+            Some(output) => record_audio(output.clone(), args, false, status())
+                .unwrap()
+                .print(),
+            None => {
+                let now = chrono::Utc::now();
+                let name = format!(
+                    "{}.wav",
+                    now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+                );
+                // let output = Text::new("What is your name?").placeholder(name).prompt();
+                let output = Text {
+                    initial_value: Some(&name),
+                    ..Text::new("Please enter the output wav file name:")
+                }
+                .prompt();
+                match output {
+                    Ok(output) => record_audio(output, args, jack, status()).unwrap().print(),
+                    Err(_) => println!("Recording cancelled."),
+                }
             }
-            #[cfg(not(all(
-                any(
-                    target_os = "linux",
-                    target_os = "dragonfly",
-                    target_os = "freebsd",
-                    target_os = "netbsd"
-                ),
-                feature = "jack"
-            )))]
-            {
-                // If JACK is not available or the platform is unsupported, pass false to not use JACK
-                match &args.output {
-                    Some(output) => {
-                        record_audio(output.clone(), args.device, false).unwrap();
-                    }
-                    None => {
-                        let now = chrono::Utc::now();
-                        let name = format!(
-                            "{}.wav",
-                            now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
-                        );
-                        // let output = Text::new("What is your name?").placeholder(name).prompt();
-                        let output = Text {
-                            initial_value: Some(&name),
-                            ..Text::new("Please enter the output wav file name:")
-                        }
-                        .prompt();
-                        match output {
-                            Ok(output) => record_audio(output, args.device, false).unwrap(),
-                            Err(_) => println!("Recording cancelled."),
-                        }
-                    }
-                };
+        },
+        Commands::Play(args) if !args.inputs.is_empty() => {
+            let files = playlist::expand(&args.inputs).unwrap();
+            if files.is_empty() {
+                println!("No wav or flac files found");
+            } else {
+                play_audio(files, args, false, status()).unwrap();
             }
         }
-        Commands::Play(args) => {
-            // Pass the respective JACK usage flag to play_audio based on compile-time detection
-            #[cfg(all(
-                any(
-                    target_os = "linux",
-                    target_os = "dragonfly",
-                    target_os = "freebsd",
-                    target_os = "netbsd"
-                ),
-                feature = "jack"
-            ))]
-            {
-                // If we're on the right platform and JACK is enabled, pass true to use JACK for playback
-                match &args.input {
-                    Some(input) => play_audio(input, args.device, false).unwrap(),
-                    None => {
-                        let mut options: Vec<String> = vec![];
-                        // check current directory for wav files
-                        let files = std::fs::read_dir(".").unwrap();
-                        for file in files {
-                            let file = file.unwrap();
-                            let path = file.path().clone();
-                            let path = path.to_str().unwrap();
-                            if path.ends_with(".wav") {
-                                options.push(path.into());
-                            }
-                        }
-                        if options.is_empty() {
-                            println!("No wav files found in current directory");
-                        } else {
-                            let ans: Result<String, InquireError> =
-                                Select::new("Select a wav file to play", options).prompt();
-                            match ans {
-                                Ok(input) => play_audio(&input, args.device, cli.jack).unwrap(),
-                                Err(_) => println!("Playback cancelled."),
-                            }
-                        }
+        Commands::Play(args) => match browser::browse(Path::new("."), args.device, jack) {
+            Ok(Some(input)) => play_audio(vec![input], args, jack, status()).unwrap(),
+            Ok(None) => println!("Playback cancelled."),
+            Err(e) => println!("{} {}", "Failed to browse files:".red().bold(), e),
+        },
+        Commands::Overdub(args) => overdub(args, jack, status()).unwrap(),
+        Commands::Monitor(args) => {
+            let buffer_size = args.buffer_size.unwrap_or(1024);
+            start_monitoring(buffer_size, status()).unwrap();
+        }
+        Commands::Repair(args) => {
+            let path = match &args.output {
+                Some(output) => {
+                    if let Err(e) = std::fs::copy(&args.input, output) {
+                        eprintln!(
+                            "{} {} to {}: {}",
+                            "Failed to copy".red().bold(),
+                            args.input,
+                            output,
+                            e
+                        );
+                        return;
                     }
+                    output
                 }
-            }
-            #[cfg(not(all(
-                any(
-                    target_os = "linux",
-                    target_os = "dragonfly",
-                    target_os = "freebsd",
-                    target_os = "netbsd"
-                ),
-                feature = "jack"
-            )))]
-            {
-                // If JACK is not available or the platform is unsupported, pass false to not use JACK
-                match &args.input {
-                    Some(input) => play_audio(input, args.device, false).unwrap(),
-                    None => {
-                        let mut options: Vec<String> = vec![];
-                        // check current directory for wav files
-                        let files = std::fs::read_dir(".").unwrap();
-                        for file in files {
-                            let file = file.unwrap();
-                            let path = file.path().clone();
-                            let path = path.to_str().unwrap();
-                            if path.ends_with(".wav") {
-                                options.push(path.into());
-                            }
-                        }
-                        if options.is_empty() {
-                            println!("No wav files found in current directory");
-                        } else {
-                            let ans: Result<String, InquireError> =
-                                Select::new("Select a wav file to play", options).prompt();
-                            match ans {
-                                Ok(input) => play_audio(&input, args.device, false).unwrap(),
-                                Err(_) => println!("Playback cancelled."),
-                            }
-                        }
+                None => &args.input,
+            };
+            match wav::repair(std::path::Path::new(path)) {
+                Ok(repaired) => {
+                    println!(
+                        "{} {}: {} frames ({:.2}s) recovered",
+                        "Repaired".green().bold(),
+                        path,
+                        repaired.frames.to_string().cyan(),
+                        repaired.frames as f64 / repaired.spec.sample_rate as f64
+                    );
+                    if repaired.truncated > 0 {
+                        println!(
+                            "{}",
+                            format!(
+                                "Dropped {} trailing bytes of an incomplete frame.",
+                                repaired.truncated
+                            )
+                            .yellow()
+                        );
                     }
                 }
+                Err(e) => eprintln!("{} {}: {}", "Failed to repair".red().bold(), path, e),
             }
         }
-        Commands::Monitor(args) => {
-            let buffer_size = args.buffer_size.unwrap_or(1024);
-            start_monitoring(buffer_size).unwrap();
+        Commands::Stats(args) => {
+            if let Err(e) = stats::print_stats(args) {
+                println!("{} {}", "Failed to analyse:".red().bold(), e);
+            }
+        }
+        Commands::Trim(args) => {
+            if let Err(e) = edit::trim(args) {
+                eprintln!("{} {}", "Failed to trim:".red().bold(), e);
+            }
+        }
+        Commands::Fade(args) => {
+            if let Err(e) = edit::fade(args) {
+                eprintln!("{} {}", "Failed to fade:".red().bold(), e);
+            }
+        }
+        Commands::Gain(args) => {
+            if let Err(e) = edit::gain(args) {
+                eprintln!("{} {}", "Failed to apply gain:".red().bold(), e);
+            }
+        }
+        Commands::Normalize(args) => {
+            if let Err(e) = edit::normalize(args) {
+                eprintln!("{} {}", "Failed to normalize:".red().bold(), e);
+            }
+        }
+        Commands::SplitSilence(args) => {
+            if let Err(e) = split::split_silence(args) {
+                eprintln!("{} {}", "Failed to split:".red().bold(), e);
+            }
         }
         Commands::List => {
             let host = cpal::default_host();
@@ -10,7 +10,7 @@ use std::{
 use anyhow::Result;
 use cpal::{
     traits::{DeviceTrait, HostTrait, StreamTrait},
-    SizedSample, SupportedStreamConfig,
+    Stream, SupportedStreamConfig,
 };
 
 use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
@@ -31,7 +31,11 @@ use ratatui::{
 
 use ratatui::style::Modifier;
 
-pub fn start_monitoring(buffer_length: usize) -> Result<()> {
+use crate::analysis::{channel_levels, to_db};
+use crate::convert::{float_input_stream, float_output_stream};
+use crate::status::Status;
+
+pub fn start_monitoring(buffer_length: usize, status: Arc<Status>) -> Result<()> {
     // let rb = HeapRb::<f32>::new(buffer_length);
     let (ui_tx, ui_rx) = unbounded();
     // let shared_waveform_data = Arc::new(Mutex::new(rb));
@@ -69,115 +73,115 @@ pub fn start_monitoring(buffer_length: usize) -> Result<()> {
         ));
     }
 
-    let config = SupportedStreamConfig::new(
+    // Both ends run in their own sample format; samples pass between them as floats.
+    let input_config = SupportedStreamConfig::new(
         2,
         input_config.sample_rate(),
         input_config.buffer_size().clone(),
         input_config.sample_format(),
     );
+    let output_config = SupportedStreamConfig::new(
+        2,
+        output_config.sample_rate(),
+        output_config.buffer_size().clone(),
+        output_config.sample_format(),
+    );
+
+    let _streams = build_stream(
+        &input_device,
+        input_config,
+        &output_device,
+        output_config,
+        Arc::clone(&is_monitoring),
+        ui_tx,
+        // shared_waveform_data_for_audio_thread,
+        buffer_length,
+        status.clone(),
+    )?;
 
-    let stream_format = input_config.sample_format();
-
-    match stream_format {
-        cpal::SampleFormat::F32 => build_stream::<f32>(
-            &input_device,
-            &config.clone().into(),
-            &output_device,
-            &config.clone().into(),
-            Arc::clone(&is_monitoring),
-            ui_tx,
-            // shared_waveform_data_for_audio_thread,
-            buffer_length,
-        )?,
-        cpal::SampleFormat::I16 => build_stream::<i16>(
-            &input_device,
-            &config.clone().into(),
-            &output_device,
-            &config.clone().into(),
-            Arc::clone(&is_monitoring),
-            ui_tx,
-            // shared_waveform_data_for_audio_thread,
-            buffer_length,
-        )?,
-        cpal::SampleFormat::U16 => build_stream::<u16>(
-            &input_device,
-            &config.clone().into(),
-            &output_device,
-            &config.clone().into(),
-            Arc::clone(&is_monitoring),
-            ui_tx,
-            // shared_waveform_data_for_audio_thread,
-            buffer_length,
-        )?,
-        _ => return Err(anyhow::anyhow!("Unsupported sample format")),
-    };
-
-    record_tui(ui_rx, is_monitoring, &selected_input, &selected_output)?;
+    record_tui(
+        ui_rx,
+        is_monitoring,
+        &selected_input,
+        &selected_output,
+        &status,
+    )?;
     Ok(())
 }
 
-fn build_stream<T>(
+/// Starts the input and output streams; they run until dropped.
+#[allow(clippy::too_many_arguments)]
+fn build_stream(
     input_device: &cpal::Device,
-    input_config: &cpal::StreamConfig,
+    input_config: SupportedStreamConfig,
     output_device: &cpal::Device,
-    output_config: &cpal::StreamConfig,
+    output_config: SupportedStreamConfig,
     is_monitoring: Arc<AtomicBool>,
     ui_tx: Sender<Vec<f32>>,
     // shared_waveform_data: Arc<Mutex<SharedRb<Heap<f32>>>>,
     buffer_length: usize,
-) -> Result<(), anyhow::Error>
-where
-    T: cpal::Sample + Send + 'static + Default + SizedSample + Into<f32>,
-{
-    let (tx, rx) = bounded::<T>(buffer_length);
+    status: Arc<Status>,
+) -> Result<(Stream, Stream), anyhow::Error> {
+    let (tx, rx) = bounded::<f32>(buffer_length);
+    let samples_per_second = (input_config.sample_rate().0 * input_config.channels() as u32) as f64;
+    let status_for_input = status.clone();
+    let status_for_input_err = status.clone();
+    let status_for_output = status.clone();
     // let is_monitoring_clone = Arc::clone(&is_monitoring);
-    let input_stream = input_device.build_input_stream(
+    let input_stream = float_input_stream(
+        input_device,
         input_config,
-        move |data: &[T], _: &cpal::InputCallbackInfo| {
+        move |data, _| {
             if is_monitoring.load(Ordering::SeqCst) {
-                // let mut waveform = shared_waveform_data.lock();
-
-                let waveform: Vec<f32> = data
-                    .iter()
-                    .map(|&sample| {
-                        let s: f32 = sample.into();
-                        s
-                    })
-                    .collect();
-                ui_tx.send(waveform).ok();
+                ui_tx.send(data.to_vec()).ok();
 
+                let mut dropped = 0;
                 for &sample in data.iter() {
-                    // let sample_f32: f32 = sample.into();
-                    // (*waveform).push_overwrite(sample_f32);
-                    if tx.send(sample).is_err() {
-                        eprintln!("Buffer overflow, dropping sample");
+                    if tx.try_send(sample).is_err() {
+                        dropped += 1;
                     }
                 }
+                if dropped > 0 {
+                    status_for_input
+                        .overrun(Duration::from_secs_f64(dropped as f64 / samples_per_second));
+                }
             }
         },
-        move |err| {
-            eprintln!("Error occurred on input stream: {}", err);
-        },
-        None,
+        move |err| status_for_input_err.input_error(err),
     )?;
 
-    let output_stream = output_device.build_output_stream(
+    let mut started = false;
+    let output_stream = float_output_stream(
+        output_device,
         output_config,
-        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+        false,
+        move |data, _| {
+            let mut missing = 0;
             for sample in data.iter_mut() {
-                *sample = rx.recv().unwrap_or_default();
+                *sample = match rx.try_recv() {
+                    Ok(sample) => {
+                        started = true;
+                        sample
+                    }
+                    Err(_) => {
+                        missing += 1;
+                        0.
+                    }
+                };
+            }
+            // The output starts before any input arrives; that isn't an underrun.
+            if started && missing > 0 {
+                status_for_output
+                    .underrun(Duration::from_secs_f64(missing as f64 / samples_per_second));
             }
         },
-        move |err| {
-            eprintln!("Error occurred on output stream: {}", err);
-        },
-        None,
+        move |err| status.output_error(err),
     )?;
 
     input_stream.play()?;
     output_stream.play()?;
 
-    Ok(())
+    Ok((input_stream, output_stream))
 }
 
 fn record_tui(
@@ -185,6 +189,7 @@ fn record_tui(
     is_monitoring: Arc<AtomicBool>,
     selected_input: &str,
     selected_output: &str,
+    status: &Status,
 ) -> Result<()> {
     enable_raw_mode()?;
     execute!(stdout(), EnterAlternateScreen)?;
@@ -201,6 +206,7 @@ fn record_tui(
             waveform_data,
             selected_input,
             selected_output,
+            status,
         )?;
         let refresh_interval = Duration::from_millis(100);
         if event::poll(refresh_interval)? {
@@ -223,6 +229,7 @@ fn draw_rec_waveform(
     shared_waveform_data: Vec<f32>,
     selected_input: &str,
     selected_output: &str,
+    status: &Status,
 ) -> Result<()> {
     terminal.draw(|f| {
         let waveform: Vec<f32> = shared_waveform_data.to_vec();
@@ -238,12 +245,13 @@ fn draw_rec_waveform(
                     // Constraint::Length(4),
                     Constraint::Length(3),
                     Constraint::Length(3),
+                    Constraint::Length(1),
                     Constraint::Min(3),
                 ]
                 .as_ref(),
             );
 
-        let [title, indicator, rect_left, rect_right, help] = vertical.areas(f.size());
+        let [title, indicator, rect_left, rect_right, status_line, help] = vertical.areas(f.size());
 
         let devices = Paragraph::new(Text::raw(format!(
             "INPUT: {};\t  OUTPUT: {};",
@@ -265,16 +273,17 @@ fn draw_rec_waveform(
 
         f.render_widget(Paragraph::new(label), help);
 
-        let level = calculate_level(&waveform);
+        f.render_widget(status.widget(), status_line);
 
-        if level.len() < 2 {
+        if waveform.is_empty() {
             return;
         }
-        let left = (level[0].0 * 90.) as u64;
-        let right = (level[1].0 * 90.) as u64;
+        let level = channel_levels(&waveform, 2);
+        let left = (level[0].rms * 90.) as u64;
+        let right = (level[1].rms * 90.) as u64;
 
-        let db_left = (20. * level[0].0.log10()) as i32;
-        let db_right = (20. * level[1].0.log10()) as i32;
+        let db_left = to_db(level[0].rms as f64) as i32;
+        let db_right = to_db(level[1].rms as f64) as i32;
         // audio clip indicator
         let color = if left > 90 || right > 90 {
             Color::Red
@@ -346,7 +355,7 @@ fn draw_rec_waveform(
                 ),
                 Style::new().italic().bold().fg(Color::White),
             ))
-            .ratio(level[0].0 as f64);
+            .ratio(level[0].rms as f64);
         f.render_widget(g, rect_left);
 
         let g = Gauge::default()
@@ -362,7 +371,7 @@ fn draw_rec_waveform(
                 ),
                 Style::new().italic().bold().fg(Color::White),
             ))
-            .ratio(level[1].0 as f64);
+            .ratio(level[1].rms as f64);
         f.render_widget(g, rect_right);
 
         // let peak_left = (level[0].1 * 90.) as u64;
@@ -387,19 +396,3 @@ fn draw_rec_waveform(
     })?;
     Ok(())
 }
-
-fn calculate_level(samples: &[f32]) -> Vec<(f32, f32)> {
-    let mut v = vec![];
-    for frame in samples.chunks(2) {
-        let square_sum: f32 = frame.iter().map(|&sample| (sample).powi(2)).sum();
-        let mean: f32 = square_sum / frame.len() as f32;
-        let rms = mean.sqrt();
-
-        let peak = frame
-            .iter()
-            .map(|&sample| sample.abs())
-            .max_by(|a, b| a.partial_cmp(b).unwrap());
-        v.push((rms, peak.unwrap()));
-    }
-    v
-}
@@ -0,0 +1,441 @@
+use anyhow::Result;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{OutputCallbackInfo, StreamInstant};
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use crossterm::event::{self, KeyCode};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use hound::WavSpec;
+use parking_lot::Mutex;
+use ratatui::style::Modifier;
+use ratatui::text::Span;
+use ratatui::widgets::{Block, Borders, Gauge, Paragraph};
+use ratatui::{
+    layout::{Constraint, Direction, Layout},
+    prelude::{CrosstermBackend, Terminal},
+    style::{Color, Style},
+};
+use std::collections::VecDeque;
+use std::io::stdout;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use crate::analysis::to_db;
+use crate::cli::{OverdubArgs, RecFormat};
+use crate::convert::{float_output_stream, Samples};
+use crate::decode::{read_audio, resample};
+use crate::raw::RawSpec;
+use crate::record::build_float_input;
+use crate::status::{GapDetector, Status};
+use crate::writer::{RecordingWriter, SplitPolicy};
+
+/// When the first output buffer was handed to the driver, and when it is heard.
+/// Set once by the output callback, so it never waits on the writer thread.
+type OutputStart = Arc<OnceLock<(StreamInstant, StreamInstant)>>;
+
+/// Plays the backing track, mixed with the monitored input if enabled.
+struct Mixer {
+    backing: Vec<Vec<f32>>,
+    position: Arc<AtomicUsize>,
+    started: OutputStart,
+    monitor_rx: Option<Receiver<Vec<f32>>>,
+    monitor: VecDeque<f32>,
+    input_channels: usize,
+    gaps: GapDetector,
+    status: Arc<Status>,
+}
+
+impl Mixer {
+    fn fill(&mut self, data: &mut [f32], info: &OutputCallbackInfo) {
+        if self.started.get().is_none() {
+            let _ = self
+                .started
+                .set((info.timestamp().callback, info.timestamp().playback));
+        }
+
+        let channels = self.backing.len();
+        let frames = data.len() / channels;
+        if let Some(lost) = self.gaps.check(info.timestamp().playback, frames) {
+            self.status.underrun(lost);
+        }
+        if let Some(rx) = &self.monitor_rx {
+            while let Ok(input) = rx.try_recv() {
+                self.monitor.extend(input);
+            }
+            // Drop what the output can't keep up with, so monitoring doesn't lag.
+            let keep = frames * 2 * self.input_channels;
+            if self.monitor.len() > keep {
+                self.monitor.drain(..self.monitor.len() - keep);
+            }
+        }
+
+        let mut position = self.position.load(Ordering::Relaxed);
+        for frame in data.chunks_mut(channels) {
+            let monitored = self.monitor.len() >= self.input_channels;
+            for (channel, sample) in frame.iter_mut().enumerate() {
+                let backing = self.backing[channel].get(position).copied().unwrap_or(0.);
+                let monitor = if monitored {
+                    self.monitor[channel % self.input_channels]
+                } else {
+                    0.
+                };
+                *sample = backing + monitor;
+            }
+            if monitored {
+                self.monitor.drain(..self.input_channels);
+            }
+            position += 1;
+        }
+        self.position.store(position, Ordering::Relaxed);
+    }
+}
+
+/// Seconds from `from` to `to`, negative if `to` is earlier.
+fn seconds_between(from: StreamInstant, to: StreamInstant) -> f64 {
+    match to.duration_since(&from) {
+        Some(duration) => duration.as_secs_f64(),
+        None => -from.duration_since(&to).unwrap_or_default().as_secs_f64(),
+    }
+}
+
+pub fn overdub(args: &OverdubArgs, jack: bool, status: Arc<Status>) -> Result<()> {
+    #[cfg(all(
+        any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        ),
+        feature = "jack"
+    ))]
+    let host = if jack {
+        cpal::host_from_id(cpal::available_hosts()
+            .into_iter()
+            .find(|id| *id == cpal::HostId::Jack)
+            .expect(
+                "make sure --features jack is specified. only works on OSes where jack is available",
+            )).expect("jack host unavailable")
+    } else {
+        cpal::default_host()
+    };
+
+    #[cfg(any(
+        not(any(
+            target_os = "linux",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "netbsd"
+        )),
+        not(feature = "jack")
+    ))]
+    let host = {
+        assert!(
+            !jack,
+            "jack is only supported on linux, dragonfly, freebsd, and netbsd"
+        );
+        cpal::default_host()
+    };
+
+    let output_device = match args.output_device {
+        Some(index) => host.output_devices()?.nth(index as usize),
+        None => host.default_output_device(),
+    }
+    .expect("failed to find output device");
+    let input_device = match args.input_device {
+        Some(index) => host.input_devices()?.nth(index as usize),
+        None => host.default_input_device(),
+    }
+    .expect("failed to find input device");
+
+    let output_config = output_device.default_output_config()?;
+    let input_config = input_device.default_input_config()?;
+    let sample_rate = output_config.sample_rate().0;
+    if input_config.sample_rate().0 != sample_rate {
+        return Err(anyhow::anyhow!(
+            "the input device runs at {} Hz but the output device at {} Hz; set them to the same rate",
+            input_config.sample_rate().0,
+            sample_rate
+        ));
+    }
+    let output_channels = output_config.channels() as usize;
+    let input_channels = input_config.channels() as usize;
+
+    let raw = RawSpec::from_args(&args.raw);
+    let (backing_rate, mut backing) = read_audio(Path::new(&args.backing), raw.as_ref())?;
+    for _ in backing.len()..output_channels {
+        backing.push(backing[0].clone());
+    }
+    let backing = resample(
+        &backing[..output_channels],
+        backing_rate as f64,
+        sample_rate as f64,
+    );
+    let length = backing[0].len();
+
+    let spec = WavSpec {
+        channels: input_channels as u16,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let format = args
+        .format
+        .or_else(|| RecFormat::from_path(&args.output))
+        .unwrap_or_else(|| RecFormat::native(&spec));
+    let path = format.output_path(&args.output);
+
+    let position = Arc::new(AtomicUsize::new(0));
+    let started: OutputStart = Arc::new(OnceLock::new());
+    let compensation = Arc::new(Mutex::new(None));
+    let is_recording = Arc::new(AtomicBool::new(true));
+    let (input_tx, input_rx) = unbounded();
+    let (monitor_tx, monitor_rx) = unbounded();
+    let (ui_tx, ui_rx) = unbounded();
+
+    let mut mixer = Mixer {
+        backing,
+        position: position.clone(),
+        started: started.clone(),
+        monitor_rx: args.monitor.then_some(monitor_rx),
+        monitor: VecDeque::new(),
+        input_channels,
+        gaps: GapDetector::new(sample_rate),
+        status: status.clone(),
+    };
+    let status_for_output = status.clone();
+    let output_stream = float_output_stream(
+        &output_device,
+        output_config,
+        false,
+        move |data, info| mixer.fill(data, info),
+        move |err| status_for_output.output_error(err),
+    )?;
+    let status_for_err = status.clone();
+    let err_fn = move |err| status_for_err.input_error(err);
+    let input_stream = build_float_input(&input_device, input_config, input_tx, err_fn)?;
+
+    let take = Take {
+        input_rx,
+        monitor_tx: args.monitor.then_some(monitor_tx),
+        ui_tx,
+        started,
+        latency: args.latency,
+        compensation: compensation.clone(),
+        channels: input_channels,
+        sample_rate,
+        length,
+        status: status.clone(),
+    };
+    let writer = RecordingWriter::create(
+        &path,
+        format,
+        format.spec(&spec),
+        None,
+        SplitPolicy::default(),
+        Duration::from_secs(5),
+    )?;
+    let is_recording_for_thread = is_recording.clone();
+    let writer_thread = std::thread::spawn(move || take.record(writer, is_recording_for_thread));
+
+    input_stream.play()?;
+    output_stream.play()?;
+
+    overdub_tui(
+        ui_rx,
+        &is_recording,
+        &position,
+        length,
+        sample_rate,
+        &compensation,
+        &status,
+    )?;
+    is_recording.store(false, Ordering::SeqCst);
+    output_stream.pause()?;
+    input_stream.pause()?;
+    writer_thread.join().unwrap()?;
+
+    Ok(())
+}
+
+/// Lines the input up with the backing track and writes it.
+struct Take {
+    input_rx: Receiver<(StreamInstant, Vec<f32>)>,
+    monitor_tx: Option<Sender<Vec<f32>>>,
+    ui_tx: Sender<Vec<f32>>,
+    started: OutputStart,
+    latency: Option<f64>,
+    /// The latency compensated for, in milliseconds, for the TUI.
+    compensation: Arc<Mutex<Option<f64>>>,
+    channels: usize,
+    sample_rate: u32,
+    length: usize,
+    status: Arc<Status>,
+}
+
+impl Take {
+    /// Writes the input from the moment the first backing frame is heard (or,
+    /// with a configured latency, that long after it was sent to the output)
+    /// until the take is as long as the backing track.
+    fn record(self, mut writer: RecordingWriter, is_recording: Arc<AtomicBool>) -> Result<()> {
+        let sample_rate = self.sample_rate as f64;
+        // Input frame index of the first frame in `pending`.
+        let mut start = 0i64;
+        let mut received = 0i64;
+        let mut offset: Option<i64> = None;
+        let mut pending: Vec<f32> = Vec::new();
+        let mut written = 0usize;
+        let mut gaps = GapDetector::new(self.sample_rate);
+
+        while written < self.length && is_recording.load(Ordering::SeqCst) {
+            let Ok((capture, data)) = self.input_rx.recv_timeout(Duration::from_millis(100)) else {
+                continue;
+            };
+            if let Some(lost) = gaps.check(capture, data.len() / self.channels) {
+                self.status.overrun(lost);
+            }
+            if let Some(tx) = &self.monitor_tx {
+                tx.send(data.clone()).ok();
+            }
+            self.ui_tx.send(data.clone()).ok();
+
+            if offset.is_none() {
+                if let Some(&(callback, playback)) = self.started.get() {
+                    // Input frame index captured at a given instant.
+                    let index_at =
+                        |at| received as f64 + seconds_between(capture, at) * sample_rate;
+                    let aligned = match self.latency {
+                        Some(ms) => index_at(callback) + ms / 1000. * sample_rate,
+                        None => index_at(playback),
+                    };
+                    *self.compensation.lock() =
+                        Some((aligned - index_at(callback)) / sample_rate * 1000.);
+                    offset = Some(aligned.round() as i64);
+                }
+            }
+            received += (data.len() / self.channels) as i64;
+            pending.extend(data);
+
+            let Some(offset) = offset else {
+                continue;
+            };
+            if start > offset {
+                // The input started after the backing track: pad the front.
+                let silence = (start - offset) as usize;
+                writer.write(&Samples::F32(vec![0.; silence * self.channels]))?;
+                written += silence;
+                start = offset;
+            }
+            if start < offset {
+                let frames = ((offset - start) as usize).min(pending.len() / self.channels);
+                pending.drain(..frames * self.channels);
+                start += frames as i64;
+            }
+            if start == offset {
+                let frames = (pending.len() / self.channels).min(self.length - written);
+                writer.write(&Samples::F32(pending[..frames * self.channels].to_vec()))?;
+                written += frames;
+                start += (pending.len() / self.channels) as i64;
+                pending.clear();
+            }
+        }
+
+        is_recording.store(false, Ordering::SeqCst);
+        writer.finalize()?;
+        Ok(())
+    }
+}
+
+fn overdub_tui(
+    ui_rx: Receiver<Vec<f32>>,
+    is_recording: &AtomicBool,
+    position: &AtomicUsize,
+    length: usize,
+    sample_rate: u32,
+    compensation: &Mutex<Option<f64>>,
+    status: &Status,
+) -> Result<()> {
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    terminal.hide_cursor()?;
+
+    let mut peak = 0f32;
+    while is_recording.load(Ordering::SeqCst) {
+        if event::poll(Duration::from_millis(100))? {
+            if let event::Event::Key(event) = event::read()? {
+                if event.code == KeyCode::Enter {
+                    break;
+                }
+            }
+        }
+
+        peak *= 0.7;
+        while let Ok(data) = ui_rx.try_recv() {
+            peak = data.iter().fold(peak, |peak, s| peak.max(s.abs()));
+        }
+
+        let played = position.load(Ordering::Relaxed).min(length);
+        let elapsed = played as f64 / sample_rate as f64;
+        let duration = length as f64 / sample_rate as f64;
+        let latency = match *compensation.lock() {
+            Some(ms) => format!("latency compensation: {:.1} ms", ms),
+            None => "latency compensation: measuring...".to_string(),
+        };
+
+        terminal.draw(|f| {
+            let chunks = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(
+                    [
+                        Constraint::Length(2),
+                        Constraint::Length(1),
+                        Constraint::Length(2),
+                        Constraint::Length(1),
+                        Constraint::Min(2),
+                    ]
+                    .as_ref(),
+                )
+                .split(f.size());
+
+            let gauge = Gauge::default()
+                .block(
+                    Block::default()
+                        .title(format!("OVERDUB  {:.2}s/{:.2}s", elapsed, duration))
+                        .borders(Borders::NONE),
+                )
+                .gauge_style(Style::default().fg(Color::Red).bg(Color::Black))
+                .ratio((played as f64 / length.max(1) as f64).clamp(0., 1.));
+            f.render_widget(gauge, chunks[0]);
+            f.render_widget(Paragraph::new(latency.as_str()), chunks[1]);
+
+            let peak_db = to_db(peak as f64);
+            let input = Gauge::default()
+                .block(Block::default().title("input").borders(Borders::NONE))
+                .gauge_style(Color::Green)
+                .label(if peak_db < -90. {
+                    "-inf dB".to_string()
+                } else {
+                    format!("{:.1} dB", peak_db)
+                })
+                .ratio(((peak_db + 60.) / 60.).clamp(0., 1.));
+            f.render_widget(input, chunks[2]);
+
+            let label = Span::styled(
+                "press ENTER to stop recording.",
+                Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::ITALIC | Modifier::BOLD),
+            );
+            f.render_widget(status.widget(), chunks[3]);
+            f.render_widget(Paragraph::new(label), chunks[4]);
+        })?;
+    }
+
+    disable_raw_mode()?;
+    execute!(stdout(), LeaveAlternateScreen)?;
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use crate::levels::Level;
+
+/// The levels of a whole take in at most `capacity` buckets, whatever its length.
+///
+/// Each bucket covers `span` blocks. When the buckets run out, neighbours are
+/// merged and the span doubles, so memory stays constant and resolution halves.
+pub struct Overview {
+    capacity: usize,
+    span: usize,
+    buckets: Vec<Level>,
+    pending: Level,
+    pending_squares: f32,
+    pending_blocks: usize,
+}
+
+impl Overview {
+    pub fn new(capacity: usize) -> Self {
+        Overview {
+            capacity: capacity.max(2),
+            span: 1,
+            buckets: Vec::with_capacity(capacity),
+            pending: Level::default(),
+            pending_squares: 0.,
+            pending_blocks: 0,
+        }
+    }
+
+    pub fn push(&mut self, level: Level) {
+        self.pending.min = self.pending.min.min(level.min);
+        self.pending.max = self.pending.max.max(level.max);
+        self.pending_squares += level.rms * level.rms;
+        self.pending_blocks += 1;
+        if self.pending_blocks < self.span {
+            return;
+        }
+        if self.buckets.len() == self.capacity {
+            self.buckets = self.buckets.chunks(2).map(Level::combine).collect();
+            self.span *= 2;
+            // What was pending is now half a bucket.
+            return;
+        }
+        self.buckets.push(self.pending_level());
+        self.pending = Level::default();
+        self.pending_squares = 0.;
+        self.pending_blocks = 0;
+    }
+
+    fn pending_level(&self) -> Level {
+        Level {
+            rms: (self.pending_squares / self.pending_blocks.max(1) as f32).sqrt(),
+            ..self.pending
+        }
+    }
+
+    /// Blocks per bucket.
+    pub fn span(&self) -> usize {
+        self.span
+    }
+
+    /// Every bucket so far, the unfinished one last.
+    pub fn levels(&self) -> Vec<Level> {
+        let mut levels = self.buckets.clone();
+        if self.pending_blocks > 0 {
+            levels.push(self.pending_level());
+        }
+        levels
+    }
+}
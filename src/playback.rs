@@ -1,30 +1,47 @@
 use anyhow::Result;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use crossterm::event::{self, KeyCode};
+use crossterm::event::{
+    self, DisableMouseCapture, EnableMouseCapture, KeyCode, MouseButton, MouseEventKind,
+};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
-use hound::WavReader;
+
+use crate::cli::PlayArgs;
+use crate::convert::float_output_stream;
+use crate::decode::{read_audio, resample};
+use crate::gain::Gains;
+use crate::levels::{Level, BLOCK};
+use crate::playlist::Playlist;
+use crate::raw::RawSpec;
+use crate::routing::Routing;
+use crate::status::{GapDetector, Status};
+use crate::stretch::Stretcher;
+use crate::transport::{Transport, FOREVER};
 use ratatui::style::Modifier;
 use ratatui::symbols;
 use ratatui::text::Span;
-use ratatui::widgets::{Axis, Block, Borders, Chart, Dataset, Gauge, GraphType, Paragraph};
+use ratatui::widgets::{Axis, Block, Borders, Chart, Dataset, Gauge, GraphType, Paragraph, Wrap};
 use ratatui::{
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     prelude::{CrosstermBackend, Terminal},
     style::{Color, Style},
 };
 
-use dasp_interpolate::linear::Linear;
-use dasp_signal::Signal;
+use crossbeam::channel::{unbounded, Receiver, Sender};
+use parking_lot::Mutex;
+use std::f32::consts::FRAC_PI_2;
 use std::io::stdout;
-use std::sync::atomic::AtomicUsize;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
+/// The JACK host if `jack` is set and this build supports it, else the
+/// platform's default.
 #[allow(unused_variables)]
-pub fn play_audio(file_path: &str, device: Option<u8>, jack: bool) -> Result<()> {
+pub fn output_host(jack: bool) -> cpal::Host {
     // Conditionally compile with jack if the feature is specified.
     #[cfg(all(
         any(
@@ -57,9 +74,20 @@ pub fn play_audio(file_path: &str, device: Option<u8>, jack: bool) -> Result<()>
     ))]
     let host = cpal::default_host();
 
-    let device = if device.is_none() {
+    host
+}
+
+pub fn play_audio(
+    files: Vec<PathBuf>,
+    args: &PlayArgs,
+    jack: bool,
+    status: Arc<Status>,
+) -> Result<()> {
+    let host = output_host(jack);
+
+    let device = if args.device.is_none() {
         host.default_output_device()
-    } else if let Some(index) = device {
+    } else if let Some(index) = args.device {
         host.output_devices()?.nth(index as usize)
     } else {
         panic!("failed to find output device");
@@ -70,237 +98,217 @@ pub fn play_audio(file_path: &str, device: Option<u8>, jack: bool) -> Result<()>
 
     let sys_chan = config.channels() as usize;
     let sys_sr = config.sample_rate().0 as f64;
-    let mut reader = WavReader::open(file_path).expect("failed to open wav file");
-    let spec = reader.spec();
-    let source_sr = spec.sample_rate as f64;
-
-    let num_channels = spec.channels as usize;
-    let bit = spec.bits_per_sample as usize;
-    let mut file_data: Vec<Vec<f32>> = vec![];
 
-    for _ in 0..num_channels {
-        file_data.push(Vec::new());
-    }
-
-    let mut sample_count = 0;
-
-    match spec.sample_format {
-        hound::SampleFormat::Int => match spec.bits_per_sample {
-            16 => {
-                for result in reader.samples::<i16>() {
-                    let sample = result? as f32 / i16::MAX as f32;
-                    let channel = sample_count % num_channels;
-                    file_data[channel].push(sample);
-                    sample_count += 1;
-                }
-            }
+    let setup = Setup {
+        outputs: sys_chan,
+        sample_rate: sys_sr as u32,
+        map: args.map.clone(),
+        loops: if args.looping {
+            FOREVER
+        } else {
+            args.loop_count.map_or(0, |count| count - 1)
+        },
+        speed: args.speed,
+        pitch: args.pitch,
+        raw: RawSpec::from_args(&args.raw),
+    };
+    let mut playlist = Playlist::new(files, args.shuffle, args.repeat);
+    let first = playlist.current();
+    let voice = Box::new(Voice::load(playlist.path(first), first, &setup)?);
+    let mut playing = voice.track.clone();
+
+    let status_for_err = status.clone();
+    let err_fn = move |err| status_for_err.output_error(err);
+    let status_for_stream = status.clone();
+    let mut gaps = GapDetector::new(sys_sr as u32);
+
+    let gains = Arc::new(Gains::new(KEYED_CHANNELS, args.gain));
+    let gains_for_tui = gains.clone();
+    let deck = Arc::new(Deck::default());
+    let (changes_tx, changes_rx) = unbounded();
+    let mut player = Player {
+        voice,
+        incoming: None,
+        crossfade: args
+            .crossfade
+            .map_or(0, |crossfade| (crossfade.as_secs_f64() * sys_sr) as usize),
+        faded: 0,
+        deck: deck.clone(),
+        changes: changes_tx,
+        gains,
+        // Gain changes take about 10ms, too smooth to click.
+        smoothing: 1. - (-1. / (0.01 * sys_sr as f32)).exp(),
+        output: vec![0.; sys_chan],
+    };
 
-            24 => {
-                for result in reader.samples::<i32>() {
-                    let sample = result?;
-                    let sample = if sample & (1 << 23) != 0 {
-                        (sample | !0xff_ffff) as f32
-                    } else {
-                        sample as f32
-                    };
-                    let sample = sample / (1 << 23) as f32;
-                    let channel = sample_count % num_channels;
-                    file_data[channel].push(sample);
-                    sample_count += 1;
-                }
+    let stream = float_output_stream(
+        &device,
+        config,
+        args.dither,
+        move |data, info| {
+            if let Some(lost) = gaps.check(info.timestamp().playback, data.len() / sys_chan) {
+                status_for_stream.underrun(lost);
             }
-
-            32 => {
-                for result in reader.samples::<i32>() {
-                    let sample = result? as f32 / i32::MAX as f32;
-                    let channel = sample_count % num_channels;
-                    file_data[channel].push(sample);
-                    sample_count += 1;
+            for out in data.chunks_mut(sys_chan) {
+                match player.next_frame() {
+                    Some(frame) => out.copy_from_slice(frame),
+                    None => out.fill(0.),
                 }
             }
-            _ => panic!("unsupported bit depth"),
         },
-        hound::SampleFormat::Float => {
-            for result in reader.samples::<f32>() {
-                let sample = result?;
-                let channel = sample_count % num_channels;
-                file_data[channel].push(sample);
-                sample_count += 1;
-            }
-        }
-    }
-
-    // TODO: should be able to play any chan file in any chan system
-    for i in num_channels..sys_chan {
-        file_data.push(file_data[0].clone());
-    }
-
-    let file_data_clone = file_data.clone();
-
-    let mut resampled_data: Vec<Vec<f32>> = vec![vec![]; sys_chan];
+        err_fn,
+    )?;
+    stream.play()?;
 
-    for i in 0..sys_chan {
-        let mut source = dasp_signal::from_iter(file_data[i].iter().cloned());
-        let a = source.next();
-        let b = source.next();
-        let interp = Linear::new(a, b);
-        let resampled_sig = source
-            .from_hz_to_hz(interp, source_sr, sys_sr)
-            .until_exhausted();
+    let mut loader = Loader::new(setup, deck.clone());
+    loader.request(&playlist, playlist.after(first), false);
 
-        resampled_data[i] = resampled_sig.collect();
-    }
-    let length = resampled_data[0].len();
+    // The waveform is drawn from levels of the original file, a block of
+    // frames each; the playhead is tracked in blocks too.
+    let mut view = View::new(playing.overview[0].len());
 
-    let sample_format = config.sample_format();
-    let pointer = Arc::new(AtomicUsize::new(0));
+    enable_raw_mode()?;
+    execute!(stdout(), EnterAlternateScreen, EnableMouseCapture)?;
+    let backend = CrosstermBackend::new(stdout());
+    let mut terminal = Terminal::new(backend)?;
+    terminal.hide_cursor()?;
 
-    let err_fn = |err| eprintln!("an error occurred on the output stream: {}", err);
+    let mut chart_area = Rect::default();
 
-    let stream = match sample_format {
-        cpal::SampleFormat::F32 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
-                let channels = sys_chan as usize;
-                for i in (0..data.len()).step_by(sys_chan) {
-                    let p = pointer.load(std::sync::atomic::Ordering::Relaxed);
+    loop {
+        for change in changes_rx.try_iter() {
+            match change {
+                Change::Started(track) => {
+                    playlist.select(track.file);
+                    view = View::new(track.overview[0].len());
+                    playing = track;
+                    loader.request(&playlist, playlist.after(playing.file), false);
+                }
+                // Freed here rather than in the callback.
+                Change::Retired(voice) => drop(voice),
+            }
+        }
+        loader.poll(&playlist, &status);
+        let transport_for_tui = &playing.transport;
 
-                    for j in 0..channels {
-                        if i + j < data.len() && j < resampled_data.len() && p < length {
-                            data[i + j] = resampled_data[j][p];
+        if event::poll(Duration::from_millis(100))? {
+            match event::read()? {
+                event::Event::Key(event) => match event.code {
+                    KeyCode::Enter => break,
+                    KeyCode::Char('n') => {
+                        if let Some(next) = playlist.after(playing.file) {
+                            loader.request(&playlist, Some(next), true);
                         }
                     }
-
-                    let next = if p + 1 < length - 1 { p + 1 } else { 0 };
-                    pointer.store(next, std::sync::atomic::Ordering::Relaxed);
-                }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::I16 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [i16], _: &cpal::OutputCallbackInfo| {
-                let channels = sys_chan as usize;
-                for i in (0..data.len()).step_by(sys_chan) {
-                    let p = pointer.load(std::sync::atomic::Ordering::Relaxed);
-
-                    for j in 0..channels {
-                        if i + j < data.len() && j < resampled_data.len() && p < length {
-                            data[i + j] = (resampled_data[j][p] * i16::MAX as f32) as i16;
-                        }
+                    KeyCode::Char('p') => {
+                        let previous = playlist.before(playing.file).unwrap_or(playing.file);
+                        loader.request(&playlist, Some(previous), true);
                     }
-
-                    let next = if p + 1 < length - 1 { p + 1 } else { 0 };
-                    pointer.store(next, std::sync::atomic::Ordering::Relaxed);
-                }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::U16 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [u16], _: &cpal::OutputCallbackInfo| {
-                let channels = sys_chan as usize;
-                for i in (0..data.len()).step_by(sys_chan) {
-                    let p = pointer.load(std::sync::atomic::Ordering::Relaxed);
-
-                    for j in 0..channels {
-                        if i + j < data.len() && j < resampled_data.len() && p < length {
-                            data[i + j] =
-                                ((resampled_data[j][p] * u16::MAX as f32) + u16::MAX as f32) as u16;
-                        }
+                    KeyCode::Char('s') => {
+                        playlist.toggle_shuffle();
+                        loader.request(&playlist, playlist.after(playing.file), false);
                     }
-
-                    let next = if p + 1 < length - 1 { p + 1 } else { 0 };
-                    pointer.store(next, std::sync::atomic::Ordering::Relaxed);
-                }
-            },
-            err_fn,
-            None,
-        )?,
-
-        cpal::SampleFormat::I32 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [i32], _: &cpal::OutputCallbackInfo| {
-                let channels = sys_chan as usize;
-                for i in (0..data.len()).step_by(sys_chan) {
-                    let p = pointer.load(std::sync::atomic::Ordering::Relaxed);
-
-                    for j in 0..channels {
-                        if i + j < data.len() && j < resampled_data.len() && p < length {
-                            data[i + j] = (resampled_data[j][p] * i32::MAX as f32) as i32;
-                        }
+                    KeyCode::Char('r') => {
+                        playlist.toggle_repeat();
+                        loader.request(&playlist, playlist.after(playing.file), false);
                     }
-
-                    let next = if p + 1 < length - 1 { p + 1 } else { 0 };
-                    pointer.store(next, std::sync::atomic::Ordering::Relaxed);
-                }
-            },
-            err_fn,
-            None,
-        )?,
-        cpal::SampleFormat::U32 => device.build_output_stream(
-            &config.into(),
-            move |data: &mut [u32], _: &cpal::OutputCallbackInfo| {
-                let channels = sys_chan as usize;
-                for i in (0..data.len()).step_by(sys_chan) {
-                    let p = pointer.load(std::sync::atomic::Ordering::Relaxed);
-
-                    for j in 0..channels {
-                        if i + j < data.len() && j < resampled_data.len() && p < length {
-                            data[i + j] =
-                                ((resampled_data[j][p] * u32::MAX as f32) + u32::MAX as f32) as u32;
-                        }
+                    KeyCode::Char('z') => view.zoom(0.5),
+                    KeyCode::Char('x') => view.zoom(2.),
+                    KeyCode::Char('+') | KeyCode::Char('=') => transport_for_tui.change_speed(0.05),
+                    KeyCode::Char('-') => transport_for_tui.change_speed(-0.05),
+                    KeyCode::Left => view.scroll(-0.25),
+                    KeyCode::Right => view.scroll(0.25),
+                    KeyCode::Char('a') => transport_for_tui.set_a(),
+                    KeyCode::Char('b') => transport_for_tui.set_b(),
+                    KeyCode::Char('c') => transport_for_tui.clear_ab(),
+                    KeyCode::Char('l') => transport_for_tui.toggle_loop(),
+                    KeyCode::Up => gains_for_tui.set_db(gains_for_tui.db() + 1.),
+                    KeyCode::Down => gains_for_tui.set_db(gains_for_tui.db() - 1.),
+                    KeyCode::Char('m') => gains_for_tui.toggle_mute(),
+                    KeyCode::Char(digit @ '1'..='9') => {
+                        gains_for_tui.toggle_channel_mute(digit as usize - '1' as usize)
+                    }
+                    KeyCode::F(number @ 1..=9) => gains_for_tui.toggle_solo(number as usize - 1),
+                    _ => {}
+                },
+                event::Event::Mouse(event)
+                    if matches!(
+                        event.kind,
+                        MouseEventKind::Down(MouseButton::Left)
+                            | MouseEventKind::Drag(MouseButton::Left)
+                    ) =>
+                {
+                    let column = event.column.saturating_sub(chart_area.x) as f64;
+                    if event.column >= chart_area.x && column < chart_area.width as f64 {
+                        let block = view.offset + column / chart_area.width as f64 * view.length;
+                        let frame = (block * playing.frames_per_block) as usize;
+                        transport_for_tui.seek(frame);
+                        view.follow = true;
                     }
-
-                    let next = if p + 1 < length - 1 { p + 1 } else { 0 };
-                    pointer.store(next, std::sync::atomic::Ordering::Relaxed);
-                }
-            },
-            err_fn,
-            None,
-        )?,
-        _ => panic!("unsupported sample format"),
-    };
-    stream.play()?;
-
-    enable_raw_mode()?;
-    execute!(stdout(), EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout());
-    let mut terminal = Terminal::new(backend)?;
-    terminal.hide_cursor()?;
-
-    let start_time = Instant::now();
-    let file_duration = WavReader::open(file_path)?.duration() as f32 / spec.sample_rate as f32;
-
-    loop {
-        if event::poll(Duration::from_millis(100))? {
-            if let event::Event::Key(event) = event::read()? {
-                if event.code == KeyCode::Enter {
-                    break;
                 }
+                _ => {}
             }
         }
 
-        let elapsed = start_time.elapsed().as_secs_f32();
-        if elapsed >= file_duration {
-            break; // Stop when the file duration is reached
+        if transport_for_tui.is_finished() && !loader.is_busy() && deck.is_idle() {
+            break; // Stop at the end of the playlist
+        }
+        let overview = &playing.overview;
+        let num_channels = overview.len();
+        let frames_per_block = playing.frames_per_block;
+        let file_duration = playing.data[0].len() as f64 / sys_sr;
+        let position = transport_for_tui.position();
+        let elapsed = position as f64 / sys_sr;
+        let playhead = position as f64 / frames_per_block;
+        if view.follow {
+            view.keep(playhead);
         }
 
-        let progress = elapsed / file_duration;
+        let progress = if file_duration > 0. {
+            elapsed / file_duration
+        } else {
+            0.
+        };
+
+        let seconds = |frame: usize| frame as f64 / sys_sr;
+        let loop_state = match (transport_for_tui.ab(), transport_for_tui.points()) {
+            (Some((a, b)), _) => format!("  A-B loop {:.2}s-{:.2}s", seconds(a), seconds(b)),
+            (None, (Some(a), None)) => format!("  A {:.2}s", seconds(a)),
+            (None, (None, Some(b))) => format!("  B {:.2}s", seconds(b)),
+            _ => match transport_for_tui.loops() {
+                0 => String::new(),
+                FOREVER => "  loop".to_string(),
+                loops => format!("  loop {} more", loops),
+            },
+        };
+        let mut tempo = String::new();
+        if transport_for_tui.speed() != 1. {
+            tempo.push_str(&format!("  speed {:.0}%", transport_for_tui.speed() * 100.));
+        }
+        if args.pitch != 0. {
+            tempo.push_str(&format!("  pitch {:+.1} st", args.pitch));
+        }
+        let mut order = String::new();
+        if playlist.is_shuffled() {
+            order.push_str("  shuffle");
+        }
+        if playlist.repeats() {
+            order.push_str("  repeat");
+        }
+        let volume = if gains_for_tui.is_muted() {
+            "  MUTED".to_string()
+        } else {
+            format!("  vol {:+.0} dB", gains_for_tui.db())
+        };
+        let (a, b) = transport_for_tui.points();
+        let markers: Vec<f64> = [a, b]
+            .into_iter()
+            .flatten()
+            .map(|frame| frame as f64 / frames_per_block)
+            .collect();
 
         terminal.draw(|f| {
             let size = f.size();
-            let width = size.width as usize;
-
-            // data vec is calculated here, pick width samples from the file data
-            let mut data_vec: Vec<(f64, f64)> = vec![];
-            for i in 0..width {
-                let index = (i as f32 / width as f32 * length as f32) as usize;
-                let rms = file_data_clone[0][index];
-                data_vec.push((i as f64, rms as f64));
-            }
 
             let chunks = Layout::default()
                 .direction(Direction::Vertical)
@@ -316,46 +324,528 @@ pub fn play_audio(file_path: &str, device: Option<u8>, jack: bool) -> Result<()>
             let gauge = Gauge::default()
                 .block(
                     Block::default()
-                        .title(format!("PLAYBACK  {:.2}s/{:.2}s", elapsed, file_duration))
+                        .title(format!(
+                            "PLAYBACK  {} [{}/{}]{}  {:.2}s/{:.2}s  zoom x{:.0}{}{}{}",
+                            playing.name,
+                            playlist.cursor() + 1,
+                            playlist.len(),
+                            order,
+                            elapsed,
+                            file_duration,
+                            view.total as f64 / view.length,
+                            volume,
+                            tempo,
+                            loop_state
+                        ))
                         .borders(Borders::NONE),
                 )
                 .gauge_style(Style::default().fg(Color::Blue).bg(Color::Black))
-                .percent((progress * 100.0) as u16);
-            // f.render_widget(gauge, size);
+                .ratio(progress.clamp(0., 1.));
             f.render_widget(gauge, chunks[0]);
-            let datasets = vec![Dataset::default()
-                .marker(symbols::Marker::Braille)
-                .graph_type(GraphType::Line)
-                .style(Style::default().fg(Color::Red))
-                .data(&data_vec)];
-
-            let chart = Chart::new(datasets)
-                .x_axis(
-                    Axis::default()
-                        // .title("X Axis")
-                        .style(Style::default().fg(Color::Gray))
-                        // .labels(x_labels)
-                        .bounds([0., width as f64]),
-                )
-                .y_axis(
-                    Axis::default()
-                        // .title("RMS")
-                        .style(Style::default().fg(Color::Gray))
-                        .bounds([-1.0, 1.]),
-                );
-            f.render_widget(chart, chunks[1]);
+
+            let lanes = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints(vec![
+                    Constraint::Ratio(1, num_channels as u32);
+                    num_channels
+                ])
+                .split(chunks[1]);
+            let lane_block = |channel: usize| {
+                let (flag, color) = if gains_for_tui.is_soloed(channel) {
+                    ("  SOLO", Color::Green)
+                } else if gains_for_tui.is_channel_muted(channel) {
+                    ("  MUTE", Color::DarkGray)
+                } else {
+                    ("", Color::Gray)
+                };
+                Block::default()
+                    .title(Span::styled(
+                        format!("ch{}{}", channel + 1, flag),
+                        Style::default().fg(color).add_modifier(Modifier::BOLD),
+                    ))
+                    .borders(Borders::TOP)
+            };
+            chart_area = lane_block(0).inner(lanes[0]);
+
+            // Two points per column, as the chart draws in braille.
+            let per_point = (view.length / (chart_area.width as f64 * 2.)).ceil().max(1.);
+            let points = view.length / per_point;
+            let first = view.offset as usize;
+            let last = ((view.offset + view.length).ceil() as usize).min(view.total);
+            let playhead_x = (playhead - view.offset) / per_point;
+            let playhead_data = [(playhead_x, -1.), (playhead_x, 1.)];
+            let marker_data: Vec<[(f64, f64); 2]> = markers
+                .iter()
+                .map(|marker| {
+                    let x = (marker - view.offset) / per_point;
+                    [(x, -1.), (x, 1.)]
+                })
+                .collect();
+
+            for (channel, (levels, &lane)) in overview.iter().zip(lanes.iter()).enumerate() {
+                // An empty track has no levels, though its view is a block long.
+                let last = last.min(levels.len());
+                let columns = Level::columns(&levels[first.min(last)..last], per_point as usize);
+                let max_data: Vec<(f64, f64)> = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(x, level)| (x as f64, level.max as f64))
+                    .collect();
+                let min_data: Vec<(f64, f64)> = columns
+                    .iter()
+                    .enumerate()
+                    .map(|(x, level)| (x as f64, level.min as f64))
+                    .collect();
+
+                let mut datasets = vec![
+                    Dataset::default()
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(Color::Red))
+                        .data(&max_data),
+                    Dataset::default()
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(Color::Red))
+                        .data(&min_data),
+                    Dataset::default()
+                        .marker(symbols::Marker::Braille)
+                        .graph_type(GraphType::Line)
+                        .style(Style::default().fg(Color::Yellow))
+                        .data(&playhead_data),
+                ];
+                for marker in &marker_data {
+                    datasets.push(
+                        Dataset::default()
+                            .marker(symbols::Marker::Braille)
+                            .graph_type(GraphType::Line)
+                            .style(Style::default().fg(Color::Cyan))
+                            .data(marker),
+                    );
+                }
+
+                let chart = Chart::new(datasets)
+                    .x_axis(
+                        Axis::default()
+                            .style(Style::default().fg(Color::Gray))
+                            .bounds([0., points]),
+                    )
+                    .y_axis(
+                        Axis::default()
+                            .style(Style::default().fg(Color::Gray))
+                            .bounds([-1.0, 1.]),
+                    );
+                let block = lane_block(channel);
+                let inner = block.inner(lane);
+                f.render_widget(block, lane);
+                f.render_widget(chart, inner);
+            }
             let label = Span::styled(
-                "press ENTER to exit tui and stop playback.",
+                "press ENTER to exit tui and stop playback, +/- to change speed, Z/X to zoom in/out, LEFT/RIGHT to scroll, click to seek, A/B to set loop points, C to clear them, L to loop, N/P for the next/previous file, S to shuffle, R to repeat the playlist, UP/DOWN for volume, M to mute, 1-9 to mute and F1-F9 to solo a channel.",
                 Style::default()
                     .fg(Color::Yellow)
                     .add_modifier(Modifier::ITALIC | Modifier::BOLD),
             );
 
-            f.render_widget(Paragraph::new(label), chunks[2]);
+            let [status_area, help_area] =
+                Layout::vertical([Constraint::Length(1), Constraint::Min(1)]).areas(chunks[2]);
+            f.render_widget(status.widget(), status_area);
+            f.render_widget(
+                Paragraph::new(label).wrap(Wrap { trim: true }),
+                help_area,
+            );
         })?;
     }
 
     disable_raw_mode()?;
-    execute!(stdout(), LeaveAlternateScreen)?;
+    execute!(stdout(), LeaveAlternateScreen, DisableMouseCapture)?;
     Ok(())
 }
+
+/// Channels that can be muted and soloed from the keyboard.
+const KEYED_CHANNELS: usize = 9;
+
+/// What every track is loaded with, owned so tracks can load on another thread.
+#[derive(Clone)]
+struct Setup {
+    outputs: usize,
+    sample_rate: u32,
+    map: Vec<(u16, u16)>,
+    loops: u64,
+    speed: f64,
+    pitch: f64,
+    /// How to read the files if they are headerless PCM.
+    raw: Option<RawSpec>,
+}
+
+/// A file of the playlist, decoded and resampled for the output device.
+struct Track {
+    file: usize,
+    name: String,
+    data: Vec<Vec<f32>>,
+    transport: Transport,
+    /// Levels of the original file, a block of frames each.
+    overview: Vec<Vec<Level>>,
+    frames_per_block: f64,
+}
+
+/// Plays a track: follows its transport, time-stretched and pitch-shifted
+/// when asked to, at the volume set in `gains` and mixed onto the output
+/// channels by `routing`.
+///
+/// Voices are made before they are needed, so switching tracks doesn't
+/// allocate in the output callback.
+struct Voice {
+    track: Arc<Track>,
+    routing: Routing,
+    stretcher: Stretcher,
+    stretching: bool,
+    frame: Vec<f32>,
+    /// The gain of each file channel, gliding towards its target.
+    current: Vec<f32>,
+    output: Vec<f32>,
+}
+
+impl Voice {
+    fn load(path: &Path, file: usize, setup: &Setup) -> Result<Self> {
+        let (source_sr, file_data) = read_audio(path, setup.raw.as_ref())?;
+        let num_channels = file_data.len();
+        let routing = if setup.map.is_empty() {
+            Routing::standard(num_channels, setup.outputs)
+        } else {
+            Routing::from_map(&setup.map, num_channels, setup.outputs)?
+        };
+        let data = resample(&file_data, source_sr as f64, setup.sample_rate as f64);
+        let track = Track {
+            file,
+            name: if path == Path::new("-") {
+                "stdin".to_string()
+            } else {
+                path.file_name()
+                    .unwrap_or(path.as_os_str())
+                    .to_string_lossy()
+                    .into_owned()
+            },
+            transport: Transport::new(data[0].len(), setup.loops, setup.speed),
+            data,
+            overview: file_data
+                .iter()
+                .map(|channel| Level::blocks(channel))
+                .collect(),
+            frames_per_block: BLOCK as f64 * setup.sample_rate as f64 / source_sr as f64,
+        };
+        Ok(Voice {
+            track: Arc::new(track),
+            routing,
+            stretcher: Stretcher::new(num_channels, setup.sample_rate, setup.pitch),
+            stretching: false,
+            frame: vec![0.; num_channels],
+            current: vec![0.; num_channels],
+            output: vec![0.; setup.outputs],
+        })
+    }
+
+    /// Starts at the volume already set rather than gliding up to it.
+    fn settle(&mut self, gains: &Gains) {
+        for (channel, current) in self.current.iter_mut().enumerate() {
+            *current = gains.target(channel);
+        }
+    }
+
+    /// The next frame, or `None` once the transport has reached the end.
+    fn next_frame(&mut self, gains: &Gains, smoothing: f32) -> Option<&[f32]> {
+        let transport = &self.track.transport;
+        if transport.is_finished() {
+            return None;
+        }
+        let speed = transport.speed();
+        if speed == 1. && !self.stretcher.shifts_pitch() {
+            // Played untouched, sample for sample.
+            if self.stretching {
+                self.stretcher.reset();
+                self.stretching = false;
+            }
+            let position = transport.advance();
+            for (sample, channel) in self.frame.iter_mut().zip(&self.track.data) {
+                *sample = channel.get(position).copied().unwrap_or(0.);
+            }
+        } else {
+            self.stretching = true;
+            let data = &self.track.data;
+            self.stretcher.next(&mut self.frame, speed, |frame| {
+                let position = transport.advance();
+                for (sample, channel) in frame.iter_mut().zip(data) {
+                    *sample = channel.get(position).copied().unwrap_or(0.);
+                }
+            });
+        }
+        for (channel, (sample, current)) in self.frame.iter_mut().zip(&mut self.current).enumerate()
+        {
+            *current += (gains.target(channel) - *current) * smoothing;
+            *sample *= *current;
+        }
+        self.routing.apply(&self.frame, &mut self.output);
+        Some(&self.output)
+    }
+}
+
+/// The track to play next, handed from the TUI to the output callback.
+#[derive(Default)]
+struct Deck {
+    slot: Mutex<Slot>,
+    /// Set to play the queued track right away instead of after the current one.
+    skip: AtomicBool,
+}
+
+#[derive(Default)]
+struct Slot {
+    voice: Option<Box<Voice>>,
+    /// Whether the callback ran out of audio with nothing queued.
+    idle: bool,
+}
+
+impl Deck {
+    /// Whether playback has stopped for want of another track.
+    fn is_idle(&self) -> bool {
+        let slot = self.slot.lock();
+        slot.idle && slot.voice.is_none()
+    }
+}
+
+/// Sent by the output callback when it moves on to another track. The
+/// voice it leaves is sent along, to be freed outside the callback.
+enum Change {
+    Started(Arc<Track>),
+    Retired(Box<Voice>),
+}
+
+/// Loads tracks on threads of their own and queues them on the deck.
+struct Loader {
+    setup: Setup,
+    deck: Arc<Deck>,
+    tx: Sender<(usize, bool, Result<Box<Voice>>)>,
+    rx: Receiver<(usize, bool, Result<Box<Voice>>)>,
+    /// The file being loaded, and whether it is to play right away.
+    wanted: Option<(usize, bool)>,
+}
+
+impl Loader {
+    fn new(setup: Setup, deck: Arc<Deck>) -> Self {
+        let (tx, rx) = unbounded();
+        Loader {
+            setup,
+            deck,
+            tx,
+            rx,
+            wanted: None,
+        }
+    }
+
+    /// Queues `file` to play after the current track, or instead of it if
+    /// `now`. Whatever was queued before is dropped; `None` queues nothing.
+    fn request(&mut self, playlist: &Playlist, file: Option<usize>, now: bool) {
+        let queued = {
+            let mut slot = self.deck.slot.lock();
+            self.deck.skip.store(false, Ordering::Relaxed);
+            slot.voice.take()
+        };
+        drop(queued);
+        self.wanted = file.map(|file| (file, now));
+        if let Some(file) = file {
+            let path = playlist.path(file).to_path_buf();
+            let setup = self.setup.clone();
+            let tx = self.tx.clone();
+            std::thread::spawn(move || {
+                tx.send((file, now, Voice::load(&path, file, &setup).map(Box::new)))
+                    .ok();
+            });
+        }
+    }
+
+    /// Queues the tracks that finished loading. Files that fail to load are
+    /// logged and passed over.
+    fn poll(&mut self, playlist: &Playlist, status: &Status) {
+        while let Ok((file, now, voice)) = self.rx.try_recv() {
+            if self.wanted != Some((file, now)) {
+                continue; // Asked for something else since.
+            }
+            self.wanted = None;
+            match voice {
+                Ok(voice) => {
+                    // Under the lock, so the callback sees the voice and
+                    // the skip together.
+                    let mut slot = self.deck.slot.lock();
+                    slot.voice = Some(voice);
+                    self.deck.skip.store(now, Ordering::Relaxed);
+                }
+                Err(err) => {
+                    status.log(format!("{}: {}", playlist.path(file).display(), err));
+                    let next = playlist
+                        .after(file)
+                        .filter(|&next| next != playlist.current());
+                    self.request(playlist, next, now);
+                }
+            }
+        }
+    }
+
+    /// Whether a track is still loading.
+    fn is_busy(&self) -> bool {
+        self.wanted.is_some()
+    }
+}
+
+/// Produces the frames to play, moving from track to track without a gap, or
+/// crossfading between them if asked to.
+struct Player {
+    voice: Box<Voice>,
+    /// The next track while crossfading into it.
+    incoming: Option<Box<Voice>>,
+    /// Frames of crossfade, 0 for none.
+    crossfade: usize,
+    faded: usize,
+    deck: Arc<Deck>,
+    changes: Sender<Change>,
+    gains: Arc<Gains>,
+    smoothing: f32,
+    output: Vec<f32>,
+}
+
+impl Player {
+    /// The next frame, or `None` once the playlist has run out.
+    fn next_frame(&mut self) -> Option<&[f32]> {
+        if self.deck.skip.load(Ordering::Relaxed) {
+            if let Some(voice) = self.take_queued(false) {
+                if let Some(incoming) = self.incoming.take() {
+                    self.changes.send(Change::Retired(incoming)).ok();
+                }
+                self.start(voice);
+            }
+        }
+        if self.incoming.is_none() && self.should_fade() {
+            if let Some(voice) = self.take_queued(false) {
+                voice
+                    .track
+                    .transport
+                    .set_speed(self.voice.track.transport.speed());
+                self.incoming = Some(voice);
+                self.faded = 0;
+            }
+        }
+        if self.incoming.is_none() && self.voice.track.transport.is_finished() {
+            let voice = self.take_queued(true)?;
+            self.start(voice);
+        }
+
+        match &mut self.incoming {
+            None => {
+                let frame = self.voice.next_frame(&self.gains, self.smoothing)?;
+                self.output.copy_from_slice(frame);
+            }
+            Some(incoming) => {
+                // Equal power, so the loudness holds through the fade.
+                let angle = self.faded as f32 / self.crossfade as f32 * FRAC_PI_2;
+                self.output.fill(0.);
+                if let Some(frame) = self.voice.next_frame(&self.gains, self.smoothing) {
+                    for (out, sample) in self.output.iter_mut().zip(frame) {
+                        *out += sample * angle.cos();
+                    }
+                }
+                if let Some(frame) = incoming.next_frame(&self.gains, self.smoothing) {
+                    for (out, sample) in self.output.iter_mut().zip(frame) {
+                        *out += sample * angle.sin();
+                    }
+                }
+                self.faded += 1;
+                if self.faded >= self.crossfade {
+                    if let Some(incoming) = self.incoming.take() {
+                        self.start(incoming);
+                    }
+                }
+            }
+        }
+        Some(&self.output)
+    }
+
+    /// Whether the current track is close enough to its end to fade out.
+    fn should_fade(&self) -> bool {
+        let transport = &self.voice.track.transport;
+        self.crossfade > 0
+            && transport.loops() == 0
+            && transport.ab().is_none()
+            && transport.remaining() as f64 / transport.speed() <= self.crossfade as f64
+    }
+
+    /// The queued voice, if the TUI isn't holding the deck. When `waiting`
+    /// and nothing is queued, the deck is marked idle. Taking a voice
+    /// answers a skip, so a skip is only cleared once one has been taken.
+    fn take_queued(&self, waiting: bool) -> Option<Box<Voice>> {
+        let mut slot = self.deck.slot.try_lock()?;
+        slot.idle = waiting && slot.voice.is_none();
+        let voice = slot.voice.take();
+        if voice.is_some() {
+            self.deck.skip.store(false, Ordering::Relaxed);
+        }
+        voice
+    }
+
+    /// Switches to `voice`, keeping the speed.
+    fn start(&mut self, mut voice: Box<Voice>) {
+        voice
+            .track
+            .transport
+            .set_speed(self.voice.track.transport.speed());
+        voice.settle(&self.gains);
+        let track = voice.track.clone();
+        let retired = std::mem::replace(&mut self.voice, voice);
+        self.changes.send(Change::Retired(retired)).ok();
+        self.changes.send(Change::Started(track)).ok();
+    }
+}
+
+/// The part of the file shown in the waveform, in blocks of `BLOCK` frames.
+struct View {
+    total: usize,
+    offset: f64,
+    length: f64,
+    /// Whether the view pages along with the playhead.
+    follow: bool,
+}
+
+impl View {
+    fn new(total: usize) -> Self {
+        View {
+            total: total.max(1),
+            offset: 0.,
+            length: total.max(1) as f64,
+            follow: true,
+        }
+    }
+
+    /// Scales the view around its centre.
+    fn zoom(&mut self, factor: f64) {
+        let centre = self.offset + self.length / 2.;
+        self.length = (self.length * factor).clamp(8f64.min(self.total as f64), self.total as f64);
+        self.offset = centre - self.length / 2.;
+        self.clamp();
+    }
+
+    /// Moves the view by a fraction of its length; the playhead is no longer followed.
+    fn scroll(&mut self, fraction: f64) {
+        self.offset += self.length * fraction;
+        self.follow = false;
+        self.clamp();
+    }
+
+    /// Pages to the playhead if it has left the view.
+    fn keep(&mut self, playhead: f64) {
+        if playhead < self.offset || playhead >= self.offset + self.length {
+            self.offset = playhead;
+            self.clamp();
+        }
+    }
+
+    fn clamp(&mut self) {
+        self.offset = self.offset.clamp(0., self.total as f64 - self.length);
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::Result;
+use rand::seq::SliceRandom;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use crate::decode::PLAYABLE;
+
+/// The files named on the command line, with directories replaced by the
+/// playable files inside them, recursively and in name order.
+pub fn expand(inputs: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            add_directory(path, &mut files, &mut visited)?;
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// Symlinks are followed, but each directory is only searched once, so a link
+/// back up the tree can't recurse forever.
+fn add_directory(
+    directory: &Path,
+    files: &mut Vec<PathBuf>,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<()> {
+    if !visited.insert(directory.canonicalize()?) {
+        return Ok(());
+    }
+    let mut entries = std::fs::read_dir(directory)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            add_directory(&path, files, visited)?;
+        } else if is_playable(&path) {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn is_playable(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            PLAYABLE
+                .iter()
+                .any(|playable| extension.eq_ignore_ascii_case(playable))
+        })
+}
+
+/// The order files are played in and which one is playing.
+///
+/// Files are referred to by their index in the list given to `new`, so a file
+/// keeps its number when the order is shuffled.
+pub struct Playlist {
+    files: Vec<PathBuf>,
+    order: Vec<usize>,
+    cursor: usize,
+    shuffle: bool,
+    repeat: bool,
+}
+
+impl Playlist {
+    pub fn new(files: Vec<PathBuf>, shuffle: bool, repeat: bool) -> Self {
+        let mut order: Vec<usize> = (0..files.len()).collect();
+        if shuffle {
+            order.shuffle(&mut rand::thread_rng());
+        }
+        Playlist {
+            files,
+            order,
+            cursor: 0,
+            shuffle,
+            repeat,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn path(&self, file: usize) -> &Path {
+        &self.files[file]
+    }
+
+    /// The file playing.
+    pub fn current(&self) -> usize {
+        self.order[self.cursor]
+    }
+
+    /// Where the file playing is in the play order, counting from 0.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Marks `file` as the one playing.
+    pub fn select(&mut self, file: usize) {
+        if let Some(cursor) = self.order.iter().position(|&f| f == file) {
+            self.cursor = cursor;
+        }
+    }
+
+    /// The file after `file`, if the playlist doesn't end there.
+    pub fn after(&self, file: usize) -> Option<usize> {
+        let cursor = self.order.iter().position(|&f| f == file)?;
+        if cursor + 1 < self.order.len() {
+            Some(self.order[cursor + 1])
+        } else if self.repeat {
+            Some(self.order[0])
+        } else {
+            None
+        }
+    }
+
+    /// The file before `file`; the first file has none unless repeating.
+    pub fn before(&self, file: usize) -> Option<usize> {
+        let cursor = self.order.iter().position(|&f| f == file)?;
+        if cursor > 0 {
+            Some(self.order[cursor - 1])
+        } else if self.repeat {
+            self.order.last().copied()
+        } else {
+            None
+        }
+    }
+
+    pub fn is_shuffled(&self) -> bool {
+        self.shuffle
+    }
+
+    /// Shuffles the files, or puts them back in order. Shuffling starts the
+    /// order at the file playing, so every other file is still to come.
+    pub fn toggle_shuffle(&mut self) {
+        let current = self.current();
+        self.shuffle = !self.shuffle;
+        if self.shuffle {
+            self.order.retain(|&file| file != current);
+            self.order.shuffle(&mut rand::thread_rng());
+            self.order.insert(0, current);
+            self.cursor = 0;
+        } else {
+            self.order = (0..self.files.len()).collect();
+            self.cursor = current;
+        }
+    }
+
+    pub fn repeats(&self) -> bool {
+        self.repeat
+    }
+
+    pub fn toggle_repeat(&mut self) {
+        self.repeat = !self.repeat;
+    }
+}
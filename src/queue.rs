@@ -0,0 +1,182 @@
+use hound::{SampleFormat, WavSpec};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::convert::Samples;
+
+/// How full the disk queue is, shared with the TUI.
+pub struct QueueDepth {
+    queued: AtomicUsize,
+    peak: AtomicUsize,
+    capacity: usize,
+    length: Duration,
+}
+
+impl QueueDepth {
+    /// A queue of `capacity` samples, holding `length` of audio.
+    pub fn new(capacity: usize, length: Duration) -> Self {
+        QueueDepth {
+            queued: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            capacity,
+            length,
+        }
+    }
+
+    fn update(&self, queued: usize) {
+        self.queued.store(queued, Ordering::Relaxed);
+        self.peak.fetch_max(queued, Ordering::Relaxed);
+    }
+
+    pub fn ratio(&self) -> f64 {
+        self.queued.load(Ordering::Relaxed) as f64 / self.capacity as f64
+    }
+
+    /// The fullest the queue has been during the take.
+    pub fn peak_ratio(&self) -> f64 {
+        self.peak.load(Ordering::Relaxed) as f64 / self.capacity as f64
+    }
+
+    /// How much audio the queue holds when full.
+    pub fn length(&self) -> Duration {
+        self.length
+    }
+}
+
+/// The audio callback's end of the disk queue.
+pub struct QueueWriter<T> {
+    producer: Producer<T>,
+    depth: Arc<QueueDepth>,
+}
+
+impl<T> QueueWriter<T> {
+    /// Queues a whole buffer of `len` samples, or nothing if it doesn't fit.
+    /// Never blocks or allocates, so it is safe to call from the callback.
+    pub fn push(&mut self, len: usize, samples: impl Iterator<Item = T>) -> bool {
+        let queued = match self.producer.write_chunk_uninit(len) {
+            Ok(chunk) => {
+                chunk.fill_from_iter(samples);
+                true
+            }
+            Err(_) => false,
+        };
+        self.depth
+            .update(self.depth.capacity - self.producer.slots());
+        queued
+    }
+}
+
+/// The writing end of the disk queue, in the take's native sample type.
+pub enum NativeQueue {
+    I16(QueueWriter<i16>),
+    I32(QueueWriter<i32>),
+    F32(QueueWriter<f32>),
+}
+
+enum Reader {
+    I16(Consumer<i16>),
+    I32(Consumer<i32>),
+    F32(Consumer<f32>),
+}
+
+/// The writer thread's end of the disk queue.
+pub struct QueueReader {
+    reader: Reader,
+    depth: Arc<QueueDepth>,
+}
+
+impl QueueReader {
+    /// Takes up to `max` queued samples. Buffers are queued whole, so as long
+    /// as `max` is a whole number of frames, so is the result.
+    pub fn pop(&mut self, max: usize) -> Option<Samples> {
+        fn read<T: Copy>(consumer: &mut Consumer<T>, max: usize) -> Option<Vec<T>> {
+            let chunk = consumer.read_chunk(consumer.slots().min(max)).ok()?;
+            if chunk.is_empty() {
+                return None;
+            }
+            let (first, second) = chunk.as_slices();
+            let data = [first, second].concat();
+            chunk.commit_all();
+            Some(data)
+        }
+
+        let samples = match &mut self.reader {
+            Reader::I16(consumer) => read(consumer, max).map(Samples::I16),
+            Reader::I32(consumer) => read(consumer, max).map(Samples::I32),
+            Reader::F32(consumer) => read(consumer, max).map(Samples::F32),
+        };
+        self.depth.update(self.len());
+        samples
+    }
+
+    /// Number of samples waiting to be written.
+    pub fn len(&self) -> usize {
+        match &self.reader {
+            Reader::I16(consumer) => consumer.slots(),
+            Reader::I32(consumer) => consumer.slots(),
+            Reader::F32(consumer) => consumer.slots(),
+        }
+    }
+
+    /// Whether the writing end is gone, i.e. the stream has stopped.
+    pub fn is_abandoned(&self) -> bool {
+        match &self.reader {
+            Reader::I16(consumer) => consumer.is_abandoned(),
+            Reader::I32(consumer) => consumer.is_abandoned(),
+            Reader::F32(consumer) => consumer.is_abandoned(),
+        }
+    }
+}
+
+/// A preallocated lock-free queue between the input callback and the writer
+/// thread, holding `length` of audio in the sample type of `spec`.
+///
+/// Nothing is allocated while recording. If the disk stalls for longer than
+/// the queue lasts, the callback drops input instead of memory growing
+/// without limit, and `depth` shows it coming.
+pub fn disk_queue(spec: &WavSpec, length: Duration) -> (NativeQueue, QueueReader, Arc<QueueDepth>) {
+    let frames = (length.as_secs_f64() * spec.sample_rate as f64).ceil() as usize;
+    let capacity = frames.max(1) * spec.channels as usize;
+    let depth = Arc::new(QueueDepth::new(capacity, length));
+    let (queue, reader) = match (spec.sample_format, spec.bits_per_sample) {
+        (SampleFormat::Float, _) => {
+            let (producer, consumer) = RingBuffer::new(capacity);
+            (
+                writer(producer, &depth, NativeQueue::F32),
+                Reader::F32(consumer),
+            )
+        }
+        (SampleFormat::Int, 32) => {
+            let (producer, consumer) = RingBuffer::new(capacity);
+            (
+                writer(producer, &depth, NativeQueue::I32),
+                Reader::I32(consumer),
+            )
+        }
+        (SampleFormat::Int, _) => {
+            let (producer, consumer) = RingBuffer::new(capacity);
+            (
+                writer(producer, &depth, NativeQueue::I16),
+                Reader::I16(consumer),
+            )
+        }
+    };
+    let reader = QueueReader {
+        reader,
+        depth: depth.clone(),
+    };
+    (queue, reader, depth)
+}
+
+fn writer<T>(
+    producer: Producer<T>,
+    depth: &Arc<QueueDepth>,
+    native: fn(QueueWriter<T>) -> NativeQueue,
+) -> NativeQueue {
+    native(QueueWriter {
+        producer,
+        depth: depth.clone(),
+    })
+}
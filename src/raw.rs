@@ -0,0 +1,211 @@
+use hound::{SampleFormat, WavSpec};
+
+use crate::cli::{Encoding, RawArgs};
+
+/// How to read headerless PCM.
+#[derive(Clone, Copy)]
+pub struct RawSpec {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub encoding: Encoding,
+}
+
+impl RawSpec {
+    /// The layout given with `--raw`, or `None` if the input has a header.
+    pub fn from_args(args: &RawArgs) -> Option<RawSpec> {
+        Some(RawSpec {
+            sample_rate: args.rate.filter(|_| args.raw)?,
+            channels: args.channels.unwrap_or(2),
+            encoding: args.encoding.unwrap_or(Encoding::S16le),
+        })
+    }
+}
+
+impl Encoding {
+    /// Bytes per sample.
+    pub fn width(self) -> usize {
+        match self {
+            Encoding::U8 | Encoding::Mulaw | Encoding::Alaw => 1,
+            Encoding::S16le | Encoding::S16be => 2,
+            Encoding::S24le | Encoding::S24be => 3,
+            Encoding::S32le | Encoding::S32be | Encoding::F32le | Encoding::F32be => 4,
+        }
+    }
+
+    /// The encoding that stores samples of the given layout unchanged.
+    pub fn native(spec: &WavSpec) -> Encoding {
+        match (spec.sample_format, spec.bits_per_sample) {
+            (SampleFormat::Float, _) => Encoding::F32le,
+            (SampleFormat::Int, 8) => Encoding::U8,
+            (SampleFormat::Int, 16) => Encoding::S16le,
+            (SampleFormat::Int, 24) => Encoding::S24le,
+            (SampleFormat::Int, _) => Encoding::S32le,
+        }
+    }
+
+    /// The linear samples this encoding is written from. The companded
+    /// encodings take 16-bit samples.
+    pub fn spec(self, native: &WavSpec) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            Encoding::U8 => (8, SampleFormat::Int),
+            Encoding::S16le | Encoding::S16be | Encoding::Mulaw | Encoding::Alaw => {
+                (16, SampleFormat::Int)
+            }
+            Encoding::S24le | Encoding::S24be => (24, SampleFormat::Int),
+            Encoding::S32le | Encoding::S32be => (32, SampleFormat::Int),
+            Encoding::F32le | Encoding::F32be => (32, SampleFormat::Float),
+        };
+        WavSpec {
+            bits_per_sample,
+            sample_format,
+            ..*native
+        }
+    }
+
+    fn bits(self) -> u32 {
+        match self {
+            Encoding::U8 => 8,
+            Encoding::Mulaw | Encoding::Alaw => 16,
+            _ => self.width() as u32 * 8,
+        }
+    }
+
+    /// Decodes one sample of `width` bytes to `-1.0..1.0`.
+    pub fn decode(self, b: &[u8]) -> f32 {
+        match self {
+            Encoding::U8 => (b[0] as f32 - 128.) / 128.,
+            Encoding::S16le => i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.,
+            Encoding::S16be => i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.,
+            // Placed in the top three bytes of an i32 to sign-extend.
+            Encoding::S24le => i32::from_le_bytes([0, b[0], b[1], b[2]]) as f32 / 2147483648.,
+            Encoding::S24be => i32::from_be_bytes([b[0], b[1], b[2], 0]) as f32 / 2147483648.,
+            Encoding::S32le => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.,
+            Encoding::S32be => i32::from_be_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.,
+            Encoding::F32le => f32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+            Encoding::F32be => f32::from_be_bytes([b[0], b[1], b[2], b[3]]),
+            Encoding::Mulaw => mulaw_to_linear(b[0]) as f32 / 32768.,
+            Encoding::Alaw => alaw_to_linear(b[0]) as f32 / 32768.,
+        }
+    }
+
+    /// Appends integer samples in this encoding. They are at the bit depth
+    /// of the layout from `spec`.
+    pub fn encode_int(self, samples: &[i32], out: &mut Vec<u8>) {
+        for &sample in samples {
+            match self {
+                Encoding::U8 => out.push((sample as u8) ^ 0x80),
+                Encoding::S16le => out.extend_from_slice(&(sample as i16).to_le_bytes()),
+                Encoding::S16be => out.extend_from_slice(&(sample as i16).to_be_bytes()),
+                Encoding::S24le => out.extend_from_slice(&sample.to_le_bytes()[..3]),
+                Encoding::S24be => out.extend_from_slice(&sample.to_be_bytes()[1..]),
+                Encoding::S32le => out.extend_from_slice(&sample.to_le_bytes()),
+                Encoding::S32be => out.extend_from_slice(&sample.to_be_bytes()),
+                Encoding::F32le | Encoding::F32be => {
+                    self.encode_float(&[sample as f32 / 2147483648.], out)
+                }
+                Encoding::Mulaw => out.push(linear_to_mulaw(sample as i16)),
+                Encoding::Alaw => out.push(linear_to_alaw(sample as i16)),
+            }
+        }
+    }
+
+    /// Appends float samples in this encoding, rounding them for the integer
+    /// encodings.
+    pub fn encode_float(self, samples: &[f32], out: &mut Vec<u8>) {
+        match self {
+            Encoding::F32le => {
+                for &sample in samples {
+                    out.extend_from_slice(&sample.to_le_bytes());
+                }
+            }
+            Encoding::F32be => {
+                for &sample in samples {
+                    out.extend_from_slice(&sample.to_be_bytes());
+                }
+            }
+            _ => {
+                let scale = (1u64 << (self.bits() - 1)) as f64;
+                let max = scale - 1.;
+                let ints: Vec<i32> = samples
+                    .iter()
+                    .map(|&sample| (sample as f64 * scale).round().clamp(-scale, max) as i32)
+                    .collect();
+                self.encode_int(&ints, out);
+            }
+        }
+    }
+}
+
+/// Splits interleaved headerless PCM into channels. A trailing partial frame
+/// is dropped.
+pub fn decode(bytes: &[u8], spec: &RawSpec) -> Vec<Vec<f32>> {
+    let channels = spec.channels as usize;
+    let width = spec.encoding.width();
+    let frames = bytes.len() / (width * channels);
+    let mut data = vec![Vec::with_capacity(frames); channels];
+    for (index, sample) in bytes
+        .chunks_exact(width)
+        .take(frames * channels)
+        .enumerate()
+    {
+        data[index % channels].push(spec.encoding.decode(sample));
+    }
+    data
+}
+
+/// G.711 μ-law, from 16-bit linear.
+fn linear_to_mulaw(sample: i16) -> u8 {
+    let sign = if sample < 0 { 0x80 } else { 0 };
+    let magnitude = (sample as i32).abs().min(32635) + 0x84;
+    // The bias puts the top bit at 7 or above.
+    let exponent = (31 - magnitude.leading_zeros() - 7) as i32;
+    let mantissa = (magnitude >> (exponent + 3)) & 0x0f;
+    !(sign | (exponent << 4) as u8 | mantissa as u8)
+}
+
+fn mulaw_to_linear(byte: u8) -> i16 {
+    let byte = !byte;
+    let exponent = (byte >> 4) & 0x07;
+    let mantissa = (byte & 0x0f) as i32;
+    let magnitude = (((mantissa << 3) + 0x84) << exponent) - 0x84;
+    if byte & 0x80 != 0 {
+        -magnitude as i16
+    } else {
+        magnitude as i16
+    }
+}
+
+/// G.711 A-law, from 16-bit linear.
+fn linear_to_alaw(sample: i16) -> u8 {
+    // A-law works on 13 bits.
+    let mut value = (sample >> 3) as i32;
+    let mask = if value >= 0 {
+        0xd5
+    } else {
+        value = -value - 1;
+        0x55
+    };
+    let segment = (0..8).find(|&segment| value < (0x20 << segment));
+    let encoded = match segment {
+        None => 0x7f,
+        Some(segment) if segment < 2 => (segment << 4) | ((value >> 1) & 0x0f),
+        Some(segment) => (segment << 4) | ((value >> segment) & 0x0f),
+    };
+    encoded as u8 ^ mask
+}
+
+fn alaw_to_linear(byte: u8) -> i16 {
+    let byte = byte ^ 0x55;
+    let mut value = ((byte & 0x0f) as i32) << 4;
+    let segment = (byte & 0x70) >> 4;
+    match segment {
+        0 => value += 8,
+        1 => value += 0x108,
+        _ => value = (value + 0x108) << (segment - 1),
+    }
+    if byte & 0x80 != 0 {
+        value as i16
+    } else {
+        -value as i16
+    }
+}
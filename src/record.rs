@@ -1,15 +1,19 @@
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
-use cpal::{Sample, SampleFormat, SupportedStreamConfig};
-use crossbeam::channel::{unbounded, Receiver};
+use cpal::{
+    FromSample, InputCallbackInfo, Sample, SampleFormat, StreamError, StreamInstant,
+    SupportedStreamConfig,
+};
+use crossbeam::channel::{bounded, unbounded, Receiver, Sender};
 use crossterm::event::{self, KeyCode};
 use crossterm::execute;
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
-use hound::{WavSpec, WavWriter};
+use hound::WavSpec;
+use parking_lot::Mutex;
 use ratatui::style::Modifier;
 use ratatui::symbols;
-use ratatui::widgets::{Axis, Chart, Dataset, GraphType};
+use ratatui::widgets::{Axis, Chart, Dataset, Gauge, GraphType};
 use ratatui::{
     layout::{Constraint, Direction, Layout},
     prelude::{CrosstermBackend, Terminal, Text},
@@ -18,83 +22,211 @@ use ratatui::{
     widgets::Paragraph,
     widgets::{Block, Borders},
 };
-use std::io::{stdout, Stdout};
+use std::collections::VecDeque;
+use std::io::{stderr, stdout, IsTerminal, Write};
+use std::path::PathBuf;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
 
-fn calculate_rms(samples: &[f32]) -> f64 {
-    let square_sum: f64 = samples.iter().map(|&sample| (sample as f64).powi(2)).sum();
-    let mean = square_sum / samples.len() as f64;
-    mean.sqrt()
+use crate::align::DeviceFifo;
+use crate::analysis::{to_db, CLIP_LEVEL};
+use crate::cli::{RecArgs, RecFormat};
+use crate::convert::float_input_stream;
+use crate::levels::{Decimator, Level, BLOCK};
+use crate::overview::Overview;
+use crate::queue::{disk_queue, NativeQueue, QueueDepth, QueueWriter};
+use crate::status::{GapDetector, Status};
+use crate::summary::{Summary, TakeStats};
+use crate::writer::{channel_path, device_path, RecordingWriter, SplitPolicy};
+
+/// How long a channel stays flagged after a clipped sample.
+const CLIP_HOLD: Duration = Duration::from_secs(3);
+/// A channel whose peak stays below this for the last two seconds is flagged.
+pub const SILENCE_DB: f64 = -60.;
+/// Blocks of levels the meters are taken over, about 2048 frames.
+const METER_BLOCKS: usize = 16;
+/// How much the zoomed-in view shows.
+const RECENT: Duration = Duration::from_secs(5);
+/// Buckets per channel in the whole-take view.
+const OVERVIEW_BUCKETS: usize = 2048;
+
+/// One channel's lane in the recording TUI.
+struct Lane {
+    channel: u16,
+    flag: &'static str,
+    color: Color,
+    peak_db: f64,
+    rms_db: f64,
+    /// What the chart shows, oldest first.
+    levels: Vec<Level>,
 }
 
-fn record_tui(ui_rx: Receiver<Vec<f32>>, is_recording: Arc<AtomicBool>) -> anyhow::Result<()> {
+fn record_tui(
+    levels_rx: Receiver<Level>,
+    is_recording: Arc<AtomicBool>,
+    channels: &[u16],
+    sample_rate: u32,
+    drift: &Mutex<Vec<(u8, f64)>>,
+    depth: &QueueDepth,
+    status: &Status,
+) -> anyhow::Result<()> {
     let start_time = Instant::now();
     let refresh_interval = Duration::from_millis(100);
 
+    // When the audio goes to stdout, the TUI draws on stderr.
+    let screen: Box<dyn Write> = if stdout().is_terminal() {
+        Box::new(stdout())
+    } else {
+        Box::new(stderr())
+    };
     enable_raw_mode()?;
-    execute!(stdout(), EnterAlternateScreen)?;
-    let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(screen))?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
     terminal.clear()?;
 
-    let mut shared_waveform_data = Vec::new();
+    // Recent levels are kept at full resolution and the whole take in an
+    // overview that coarsens as it grows, so memory stays the same however
+    // long the take.
+    let silence_blocks = sample_rate as usize * 2 / BLOCK;
+    let history_len = (RECENT.as_secs_f64() * sample_rate as f64) as usize / BLOCK;
+    let mut history: Vec<VecDeque<Level>> =
+        vec![VecDeque::with_capacity(history_len); channels.len()];
+    let mut overviews: Vec<Overview> = channels
+        .iter()
+        .map(|_| Overview::new(OVERVIEW_BUCKETS))
+        .collect();
+    let mut whole_take = false;
+    let mut next_channel = 0;
+    let mut last_clip: Vec<Option<Instant>> = vec![None; channels.len()];
 
     loop {
         let now = Instant::now();
         let duration = now.duration_since(start_time);
-        let recording_time = format!("Recording Time: {:.2}s", duration.as_secs_f32());
+        let mut recording_time = format!("Recording Time: {:.2}s", duration.as_secs_f32());
+        for (device, ppm) in drift.lock().iter() {
+            recording_time.push_str(&format!("   dev{} drift {:+.1} ppm", device, ppm));
+        }
 
-        while let Ok(data) = ui_rx.try_recv() {
-            shared_waveform_data.extend(data);
+        // Levels arrive a block at a time, in channel order.
+        while let Ok(level) = levels_rx.try_recv() {
+            if level.peak() >= CLIP_LEVEL {
+                last_clip[next_channel] = Some(now);
+            }
+            let lane = &mut history[next_channel];
+            if lane.len() == history_len {
+                lane.pop_front();
+            }
+            lane.push_back(level);
+            overviews[next_channel].push(level);
+            next_channel = (next_channel + 1) % channels.len();
         }
 
-        draw_rec_waveform(&mut terminal, &shared_waveform_data, recording_time)?;
+        let view_length = if whole_take {
+            let span = overviews[0].span() as f64 * BLOCK as f64 / sample_rate as f64;
+            recording_time.push_str(&format!("   whole take, {:.2}s per point", span));
+            overviews[0].levels().len()
+        } else {
+            recording_time.push_str(&format!("   last {}s", RECENT.as_secs()));
+            history_len
+        };
+
+        let lanes: Vec<Lane> = channels
+            .iter()
+            .zip(&mut history)
+            .zip(&overviews)
+            .zip(&last_clip)
+            .map(|(((&channel, recent), overview), clip)| {
+                let silence_peak = recent
+                    .iter()
+                    .rev()
+                    .take(silence_blocks)
+                    .fold(0f32, |peak, level| peak.max(level.peak()));
+                let (flag, color) = if clip.is_some_and(|at| now.duration_since(at) < CLIP_HOLD) {
+                    ("  CLIP", Color::Red)
+                } else if !recent.is_empty() && to_db(silence_peak as f64) < SILENCE_DB {
+                    ("  SILENT", Color::Yellow)
+                } else {
+                    ("", Color::Green)
+                };
+
+                let meter: Vec<Level> = recent.iter().rev().take(METER_BLOCKS).copied().collect();
+                let meter = Level::combine(&meter);
+                let levels = if whole_take {
+                    overview.levels()
+                } else {
+                    recent.make_contiguous().to_vec()
+                };
+                Lane {
+                    channel,
+                    flag,
+                    color,
+                    peak_db: to_db(meter.peak() as f64),
+                    rms_db: to_db(meter.rms as f64),
+                    levels,
+                }
+            })
+            .collect();
+
+        draw_rec_waveform(
+            &mut terminal,
+            &lanes,
+            view_length,
+            recording_time,
+            depth,
+            status,
+        )?;
 
         if event::poll(refresh_interval)? {
             if let event::Event::Key(event) = event::read()? {
-                if event.code == KeyCode::Enter {
-                    is_recording.store(false, Ordering::SeqCst);
-                    break;
+                match event.code {
+                    KeyCode::Enter => {
+                        is_recording.store(false, Ordering::SeqCst);
+                        break;
+                    }
+                    KeyCode::Char('z') => whole_take = !whole_take,
+                    _ => {}
                 }
             }
         }
     }
 
-    execute!(stdout(), LeaveAlternateScreen)?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
     disable_raw_mode()?;
     Ok(())
 }
 
+fn format_db(db: f64) -> String {
+    if db < -90. {
+        "-inf".to_string()
+    } else {
+        format!("{:.1}", db)
+    }
+}
+
+/// Draws the lanes, each chart spanning `view_length` levels.
 fn draw_rec_waveform(
-    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
-    waveform_data: &[f32],
+    terminal: &mut Terminal<CrosstermBackend<Box<dyn Write>>>,
+    lanes: &[Lane],
+    view_length: usize,
     recording_time: String,
+    depth: &QueueDepth,
+    status: &Status,
 ) -> anyhow::Result<()> {
+    let channel_count = lanes.len();
     terminal.draw(|f| {
         let size = f.size();
-        let width = size.width as usize;
-        let samples_to_use = std::cmp::min(width * 128, waveform_data.len());
-        let recent_samples = &waveform_data[waveform_data.len() - samples_to_use..];
-
-        let data_vec: Vec<(f64, f64)> = recent_samples
-            .chunks(128)
-            .enumerate()
-            .map(|(x, samples)| {
-                let rms = calculate_rms(samples);
-                let x = x as f64;
-                (x, rms)
-            })
-            .collect();
 
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints(
                 [
                     Constraint::Percentage(10),
-                    Constraint::Percentage(80),
-                    Constraint::Min(4),
+                    Constraint::Min(3),
+                    Constraint::Length(1),
+                    Constraint::Length(1),
+                    Constraint::Length(2),
                 ]
                 .as_ref(),
             )
@@ -107,40 +239,124 @@ fn draw_rec_waveform(
         f.render_widget(time_paragraph, chunks[0]);
 
         let label = Span::styled(
-            "press ENTER to exit tui and finish recording...",
+            "press ENTER to exit tui and finish recording, Z to zoom in/out...",
             Style::default()
                 .fg(Color::Yellow)
                 .add_modifier(Modifier::ITALIC | Modifier::BOLD),
         );
 
-        f.render_widget(Paragraph::new(label), chunks[2]);
+        f.render_widget(Paragraph::new(label), chunks[4]);
 
-        let datasets = vec![Dataset::default()
-            .marker(symbols::Marker::Braille)
-            .graph_type(GraphType::Line)
-            .style(Style::default().fg(Color::Red))
-            .data(&data_vec)];
+        f.render_widget(status.widget(), chunks[3]);
 
-        let chart = Chart::new(datasets)
-            .x_axis(
-                Axis::default()
-                    .style(Style::default().fg(Color::Gray))
-                    .bounds([0., width as f64]),
-            )
-            .y_axis(
-                Axis::default()
-                    .style(Style::default().fg(Color::Gray))
-                    .bounds([0., 1.]),
-            );
-        f.render_widget(chart, chunks[1]);
+        let ratio = depth.ratio();
+        let queue_color = if ratio < 0.25 {
+            Color::Green
+        } else if ratio < 0.75 {
+            Color::Yellow
+        } else {
+            Color::Red
+        };
+        let queue_gauge = Gauge::default()
+            .gauge_style(queue_color)
+            .label(format!(
+                "disk queue {:.0}% of {:.1}s, peak {:.0}%",
+                ratio * 100.,
+                depth.length().as_secs_f64(),
+                depth.peak_ratio() * 100.
+            ))
+            .ratio(ratio.clamp(0., 1.));
+        f.render_widget(queue_gauge, chunks[2]);
+
+        let areas = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(vec![
+                Constraint::Ratio(1, channel_count as u32);
+                channel_count
+            ])
+            .split(chunks[1]);
+
+        let [chart_area, _] =
+            Layout::horizontal([Constraint::Min(10), Constraint::Length(32)]).areas(areas[0]);
+        let width = chart_area.width as usize;
+        // Two points per column, as the chart draws in braille.
+        let per_point = view_length.div_ceil(width * 2).max(1);
+        let points = view_length.div_ceil(per_point);
+
+        for (lane, &area) in lanes.iter().zip(areas.iter()) {
+            let columns = Level::columns(&lane.levels, per_point);
+            let max_data: Vec<(f64, f64)> = columns
+                .iter()
+                .enumerate()
+                .map(|(x, level)| (x as f64, level.max as f64))
+                .collect();
+            let min_data: Vec<(f64, f64)> = columns
+                .iter()
+                .enumerate()
+                .map(|(x, level)| (x as f64, level.min as f64))
+                .collect();
+
+            let lane_block = Block::default()
+                .title(Span::styled(
+                    format!("ch{}{}", lane.channel, lane.flag),
+                    Style::default().fg(lane.color).add_modifier(Modifier::BOLD),
+                ))
+                .borders(Borders::TOP);
+            let inner = lane_block.inner(area);
+            f.render_widget(lane_block, area);
+
+            let [chart_area, meter_area] =
+                Layout::horizontal([Constraint::Min(10), Constraint::Length(32)]).areas(inner);
+
+            let datasets = vec![
+                Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::Red))
+                    .data(&max_data),
+                Dataset::default()
+                    .marker(symbols::Marker::Braille)
+                    .graph_type(GraphType::Line)
+                    .style(Style::default().fg(Color::Red))
+                    .data(&min_data),
+            ];
+
+            let chart = Chart::new(datasets)
+                .x_axis(
+                    Axis::default()
+                        .style(Style::default().fg(Color::Gray))
+                        .bounds([0., points as f64]),
+                )
+                .y_axis(
+                    Axis::default()
+                        .style(Style::default().fg(Color::Gray))
+                        .bounds([-1., 1.]),
+                );
+            f.render_widget(chart, chart_area);
+
+            let [peak_area, rms_area] =
+                Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(meter_area);
+            let peak_gauge = Gauge::default()
+                .gauge_style(lane.color)
+                .label(format!("peak {} dB", format_db(lane.peak_db)))
+                .ratio(((lane.peak_db + 60.) / 60.).clamp(0., 1.));
+            f.render_widget(peak_gauge, peak_area);
+            let rms_gauge = Gauge::default()
+                .gauge_style(Color::Blue)
+                .label(format!("rms  {} dB", format_db(lane.rms_db)))
+                .ratio(((lane.rms_db + 60.) / 60.).clamp(0., 1.));
+            f.render_widget(rms_gauge, rms_area);
+        }
     })?;
     Ok(())
 }
 
-pub fn record_audio(output: String, device: Option<u8>, jack: bool) -> anyhow::Result<()> {
-    let output = format!("{}.wav", output.replace(".wav", ""));
-    let (ui_tx, ui_rx) = unbounded();
-    let (writer_tx, writer_rx) = unbounded();
+pub fn record_audio(
+    output: String,
+    args: &RecArgs,
+    jack: bool,
+    status: Arc<Status>,
+) -> anyhow::Result<Summary> {
     let is_recording = Arc::new(AtomicBool::new(true));
     let is_recording_for_thread = is_recording.clone();
 
@@ -179,126 +395,511 @@ pub fn record_audio(output: String, device: Option<u8>, jack: bool) -> anyhow::R
     );
     let host = cpal::default_host();
 
-    let device = if device.is_none() {
-        host.default_input_device()
-    } else if let Some(index) = device {
-        host.input_devices()?.nth(index as usize)
+    let mut devices = if args.device.is_empty() {
+        vec![host
+            .default_input_device()
+            .expect("failed to find input device")]
     } else {
-        panic!("failed to find output device")
+        args.device
+            .iter()
+            .map(|&index| {
+                host.input_devices()?
+                    .nth(index as usize)
+                    .ok_or_else(|| anyhow::anyhow!("input device #{} not found", index))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?
+    };
+
+    let mut configs = devices
+        .iter()
+        .map(|device| device.default_input_config())
+        .collect::<Result<Vec<_>, _>>()?;
+    let sample_rate = configs[0].sample_rate();
+    if let Some(index) = configs.iter().position(|c| c.sample_rate() != sample_rate) {
+        return Err(anyhow::anyhow!(
+            "input device #{} runs at {} Hz but #{} runs at {} Hz; set them to the same rate",
+            args.device[index],
+            configs[index].sample_rate().0,
+            args.device[0],
+            sample_rate.0
+        ));
     }
-    .expect("failed to find output device");
-
-    let config = device.default_input_config().unwrap();
-    let o = output.to_owned();
-    let spec = wav_spec_from_config(&device.default_input_config().unwrap());
-
-    let recording_thread = std::thread::spawn(move || {
-        let err_fn = move |err| eprintln!("an error occurred on stream: {}", err);
-        let stream = match config.sample_format() {
-            cpal::SampleFormat::I8 => device.build_input_stream(
-                &config.into(),
-                move |data: &[i8], _: &_| {
-                    let float_data: Vec<f32> = data
-                        .iter()
-                        .map(|&sample| sample.to_float_sample())
-                        .collect();
-                    ui_tx.send(float_data.clone()).ok();
-                    writer_tx.send(float_data).ok();
-                },
-                err_fn,
-                None,
-            )?,
-            cpal::SampleFormat::I16 => device.build_input_stream(
-                &config.into(),
-                move |data: &[i16], _: &_| {
-                    let float_data: Vec<f32> = data
-                        .iter()
-                        .map(|&sample| sample.to_float_sample())
-                        .collect();
-                    ui_tx.send(float_data.clone()).ok();
-                    writer_tx.send(float_data).ok();
-                },
-                err_fn,
-                None,
-            )?,
-            cpal::SampleFormat::I32 => device.build_input_stream(
-                &config.into(),
-                move |data: &[i32], _: &_| {
-                    let float_data: Vec<f32> = data
-                        .iter()
-                        .map(|&sample| sample.to_float_sample())
-                        .collect();
-                    ui_tx.send(float_data.clone()).ok();
-                    writer_tx.send(float_data).ok();
-                },
-                err_fn,
-                None,
-            )?,
-            cpal::SampleFormat::F32 => device.build_input_stream(
-                &config.into(),
-                move |data: &[f32], _: &_| {
-                    let float_data: Vec<f32> = data.to_vec();
-                    ui_tx.send(float_data.clone()).ok();
-                    writer_tx.send(float_data).ok();
-                },
-                err_fn,
-                None,
-            )?,
-            sample_format => {
-                return Err(anyhow::Error::msg(format!(
-                    "Unsupported sample format '{sample_format}'"
-                )))
+
+    let device_channels: Vec<usize> = configs.iter().map(|c| c.channels() as usize).collect();
+    let total_channels: usize = device_channels.iter().sum();
+    let channels: Vec<u16> = if args.channels_map.is_empty() {
+        (1..=total_channels as u16).collect()
+    } else {
+        args.channels_map.clone()
+    };
+    if let Some(channel) = channels.iter().find(|&&c| c as usize > total_channels) {
+        return Err(anyhow::anyhow!(
+            "channel {} is out of range, the input has {} channels",
+            channel,
+            total_channels
+        ));
+    }
+    if let Some(channel) = channels
+        .iter()
+        .enumerate()
+        .find_map(|(index, channel)| channels[..index].contains(channel).then_some(channel))
+    {
+        return Err(anyhow::anyhow!(
+            "channel {} is listed twice in --channels-map",
+            channel
+        ));
+    }
+    let channel_map: Vec<usize> = channels.iter().map(|&c| c as usize - 1).collect();
+
+    // Several devices are merged as float, after resampling onto a common clock.
+    let native = if devices.len() == 1 {
+        wav_spec_from_config(&configs[0])
+    } else {
+        WavSpec {
+            channels: total_channels as u16,
+            sample_rate: sample_rate.0,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        }
+    };
+    let spec = WavSpec {
+        channels: channels.len() as u16,
+        ..native
+    };
+    let format = args
+        .format
+        .or(args.raw.then_some(RecFormat::Raw))
+        .or_else(|| RecFormat::from_path(&output))
+        .or(args.encoding.map(|_| RecFormat::Raw))
+        .unwrap_or_else(|| RecFormat::native(&spec));
+    if args.encoding.is_some() && format != RecFormat::Raw {
+        return Err(anyhow::anyhow!(
+            "--encoding only applies to raw output, not {}",
+            format.name()
+        ));
+    }
+    let encoding = args.encoding;
+    let to_stdout = output == "-";
+    if to_stdout {
+        if args.split_channels || args.file_per_device {
+            return Err(anyhow::anyhow!("only one file can be written to stdout"));
+        }
+        if args.split_every.is_some() || args.split_size.is_some() {
+            return Err(anyhow::anyhow!("a recording to stdout cannot be split"));
+        }
+        if args.summary_json {
+            return Err(anyhow::anyhow!(
+                "--summary-json needs a file name to write next to"
+            ));
+        }
+        if !format.streams() {
+            return Err(anyhow::anyhow!(
+                "{} cannot be written to stdout, use wav or raw",
+                format.name()
+            ));
+        }
+    }
+    let o = if to_stdout {
+        PathBuf::from("-")
+    } else {
+        format.output_path(&output)
+    };
+    let json_path = o.with_extension("json");
+
+    // The files to write, each with the indices of its channels in a frame.
+    let files: Vec<(PathBuf, Vec<usize>)> = if args.split_channels {
+        channels
+            .iter()
+            .enumerate()
+            .map(|(index, &channel)| (channel_path(&o, channel), vec![index]))
+            .collect()
+    } else if args.file_per_device && devices.len() > 1 {
+        let mut first = 0;
+        let mut files = Vec::new();
+        for (&device, &count) in args.device.iter().zip(&device_channels) {
+            let indices: Vec<usize> = (0..channel_map.len())
+                .filter(|&index| (first..first + count).contains(&channel_map[index]))
+                .collect();
+            if !indices.is_empty() {
+                files.push((device_path(&o, device), indices));
             }
-        };
-        stream.play()?;
+            first += count;
+        }
+        files
+    } else {
+        vec![(o, (0..channels.len()).collect())]
+    };
 
-        while is_recording_for_thread.load(Ordering::SeqCst) {
-            std::thread::sleep(std::time::Duration::from_millis(100));
+    let (queue, mut reader, depth) = disk_queue(&spec, args.queue);
+    // About a second of levels, in case the TUI stalls.
+    let (levels_tx, levels_rx) = bounded(spec.sample_rate as usize / BLOCK * channels.len());
+    let levels = Decimator::new(channels.len(), levels_tx);
+
+    let secondary = args.device.iter().skip(1).map(|&device| (device, 0.));
+    let drift = Arc::new(Mutex::new(secondary.collect::<Vec<_>>()));
+    let drift_for_thread = drift.clone();
+    let status_for_thread = status.clone();
+
+    let recording_thread = if devices.len() == 1 {
+        let device = devices.remove(0);
+        let config = configs.remove(0);
+        std::thread::spawn(move || {
+            record_device(
+                device,
+                config,
+                channel_map,
+                queue,
+                levels,
+                status_for_thread,
+                is_recording_for_thread,
+            )
+        })
+    } else {
+        std::thread::spawn(move || {
+            record_devices(
+                devices.into_iter().zip(configs).collect(),
+                channel_map,
+                queue,
+                levels,
+                drift_for_thread,
+                status_for_thread,
+                is_recording_for_thread,
+            )
+        })
+    };
+
+    let split = SplitPolicy {
+        every: args.split_every,
+        size: args.split_size,
+    };
+    let flush_interval = args.flush_interval;
+    let file_count = channels.len();
+    // Write about a tenth of a second at a time.
+    let chunk = (spec.sample_rate as usize / 10).max(1) * file_count;
+    let mut stats = TakeStats::new(&channels, spec.sample_rate);
+
+    let writer_thread = std::thread::spawn(move || -> anyhow::Result<(Vec<PathBuf>, TakeStats)> {
+        let spec2 = match encoding {
+            Some(encoding) => encoding.spec(&spec),
+            None => format.spec(&spec),
+        };
+        let mut writers = files
+            .iter()
+            .map(|(path, indices)| {
+                let spec = WavSpec {
+                    channels: indices.len() as u16,
+                    ..spec2
+                };
+                RecordingWriter::create(path, format, spec, encoding, split, flush_interval)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        loop {
+            // Checked before popping, so everything queued before the stream
+            // stopped is still written.
+            let stopped = reader.is_abandoned();
+            let Some(data) = reader.pop(chunk) else {
+                if stopped {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(10));
+                continue;
+            };
+            stats.add(&data);
+            for (writer, (_, indices)) in writers.iter_mut().zip(&files) {
+                if indices.len() == file_count {
+                    writer.write(&data)?;
+                } else {
+                    writer.write(&data.channels(indices, file_count))?;
+                }
+            }
         }
 
-        stream.pause()?;
-        Ok(())
+        let mut paths = Vec::new();
+        for writer in writers {
+            paths.extend(writer.finalize()?);
+        }
+        Ok((paths, stats))
     });
 
-    let writer_thread = std::thread::spawn(move || -> anyhow::Result<()> {
-        let path = std::path::Path::new(&o);
+    record_tui(
+        levels_rx,
+        is_recording.clone(),
+        &channels,
+        spec.sample_rate,
+        &drift,
+        &depth,
+        &status,
+    )?;
+    is_recording.store(false, Ordering::SeqCst);
+    recording_thread.join().unwrap()?;
+    let (paths, stats) = writer_thread.join().unwrap()?;
 
-        let spec2 = WavSpec {
-            channels: spec.channels,
-            sample_rate: spec.sample_rate,
-            bits_per_sample: spec.bits_per_sample,
-            sample_format: hound::SampleFormat::Float,
+    let summary = stats.summary(paths, format.name(), spec.sample_rate, &status);
+    if args.summary_json {
+        summary.write_json(&json_path)?;
+    }
+    Ok(summary)
+}
+
+/// What an input callback does with each buffer: checks for gaps, queues the
+/// selected channels for the disk and reduces them to levels for the TUI.
+struct Capture {
+    device_channels: usize,
+    channel_map: Vec<usize>,
+    sample_rate: u32,
+    gaps: GapDetector,
+    levels: Decimator,
+    status: Arc<Status>,
+}
+
+impl Capture {
+    fn process<S: Copy, T: Sample>(
+        &mut self,
+        data: &[S],
+        info: &InputCallbackInfo,
+        queue: &mut QueueWriter<T>,
+        convert: impl Fn(S) -> T,
+    ) where
+        f32: FromSample<T>,
+    {
+        let frames = data.len() / self.device_channels;
+        if let Some(lost) = self.gaps.check(info.timestamp().capture, frames) {
+            self.status.overrun(lost);
+        }
+        let map = &self.channel_map;
+        let convert = &convert;
+        let selected = data
+            .chunks_exact(self.device_channels)
+            .flat_map(|frame| map.iter().map(move |&channel| convert(frame[channel])));
+        if !queue.push(frames * map.len(), selected.clone()) {
+            self.status.queue_full(Duration::from_secs_f64(
+                frames as f64 / self.sample_rate as f64,
+            ));
+        }
+        self.levels.push(selected.map(f32::from_sample));
+    }
+}
+
+/// Records from a single device in its native sample format.
+fn record_device(
+    device: cpal::Device,
+    config: SupportedStreamConfig,
+    channel_map: Vec<usize>,
+    queue: NativeQueue,
+    levels: Decimator,
+    status: Arc<Status>,
+    is_recording: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let mut capture = Capture {
+        device_channels: config.channels() as usize,
+        channel_map,
+        sample_rate: config.sample_rate().0,
+        gaps: GapDetector::new(config.sample_rate().0),
+        levels,
+        status: status.clone(),
+    };
+    let err_fn = move |err| status.input_error(err);
+    let stream = match (config.sample_format(), queue) {
+        (SampleFormat::I8, NativeQueue::I16(mut queue)) => device.build_input_stream(
+            &config.into(),
+            move |data: &[i8], info: &InputCallbackInfo| {
+                // Widened losslessly, see `wav_spec_from_config`.
+                capture.process(data, info, &mut queue, |sample| (sample as i16) << 8)
+            },
+            err_fn,
+            None,
+        )?,
+        (SampleFormat::I16, NativeQueue::I16(mut queue)) => device.build_input_stream(
+            &config.into(),
+            move |data: &[i16], info: &InputCallbackInfo| {
+                capture.process(data, info, &mut queue, |sample| sample)
+            },
+            err_fn,
+            None,
+        )?,
+        (SampleFormat::I32, NativeQueue::I32(mut queue)) => device.build_input_stream(
+            &config.into(),
+            move |data: &[i32], info: &InputCallbackInfo| {
+                capture.process(data, info, &mut queue, |sample| sample)
+            },
+            err_fn,
+            None,
+        )?,
+        (SampleFormat::F32, NativeQueue::F32(mut queue)) => device.build_input_stream(
+            &config.into(),
+            move |data: &[f32], info: &InputCallbackInfo| {
+                capture.process(data, info, &mut queue, |sample| sample)
+            },
+            err_fn,
+            None,
+        )?,
+        (sample_format, _) => {
+            return Err(anyhow::Error::msg(format!(
+                "Unsupported sample format '{sample_format}'"
+            )))
+        }
+    };
+    stream.play()?;
+
+    while is_recording.load(Ordering::SeqCst) {
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+
+    stream.pause()?;
+    Ok(())
+}
+
+/// Records from several devices at once and merges them into one stream of
+/// frames, the first device's channels first.
+///
+/// The first device is the clock master. The others are resampled onto its
+/// clock so they stay aligned however long the take, and `drift` is updated
+/// with how far each one runs off it.
+fn record_devices(
+    inputs: Vec<(cpal::Device, SupportedStreamConfig)>,
+    channel_map: Vec<usize>,
+    queue: NativeQueue,
+    mut levels: Decimator,
+    drift: Arc<Mutex<Vec<(u8, f64)>>>,
+    status: Arc<Status>,
+    is_recording: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
+    let NativeQueue::F32(mut queue) = queue else {
+        return Err(anyhow::anyhow!("several devices are merged as float"));
+    };
+    let sample_rate = inputs[0].1.sample_rate().0;
+    // 100ms of buffering absorbs the callback jitter of every device.
+    let target = sample_rate as usize / 10;
+    let total_channels: usize = inputs.iter().map(|(_, c)| c.channels() as usize).sum();
+
+    let mut fifos: Vec<DeviceFifo> = inputs
+        .iter()
+        .map(|(_, config)| DeviceFifo::new(config.channels() as usize, sample_rate, target))
+        .collect();
+    let mut gaps: Vec<GapDetector> = fifos
+        .iter()
+        .map(|_| GapDetector::new(sample_rate))
+        .collect();
+    let mut streams = Vec::new();
+    let mut receivers = Vec::new();
+    for (device, config) in inputs {
+        let (tx, rx) = unbounded();
+        let status = status.clone();
+        let err_fn = move |err| status.input_error(err);
+        streams.push(build_float_input(&device, config, tx, err_fn)?);
+        receivers.push(rx);
+    }
+    for stream in &streams {
+        stream.play()?;
+    }
+
+    let mut primed = false;
+    let mut parts = vec![Vec::new(); fifos.len()];
+    let mut merged = Vec::new();
+    while is_recording.load(Ordering::SeqCst) {
+        let Ok((capture, data)) = receivers[0].recv_timeout(Duration::from_millis(100)) else {
+            continue;
         };
+        if let Some(lost) = gaps[0].check(capture, data.len() / fifos[0].channels()) {
+            status.overrun(lost);
+        }
+        fifos[0].push(capture, &data);
+        for ((fifo, rx), gaps) in fifos.iter_mut().zip(&receivers).zip(&mut gaps).skip(1) {
+            while let Ok((capture, data)) = rx.try_recv() {
+                if let Some(lost) = gaps.check(capture, data.len() / fifo.channels()) {
+                    status.overrun(lost);
+                }
+                fifo.push(capture, &data);
+            }
+        }
 
-        let mut writer = WavWriter::create(path, spec2).unwrap();
+        if !primed {
+            // Wait for every device to deliver, then line them up on the
+            // capture time of the latest common frame.
+            if fifos.iter().any(|fifo| fifo.frames() < target * 2) {
+                continue;
+            }
+            if let Some(end) = fifos.iter().filter_map(|fifo| fifo.newest()).min() {
+                for fifo in &mut fifos {
+                    fifo.prime(end);
+                }
+            }
+            primed = true;
+            continue;
+        }
 
-        while let Ok(data) = writer_rx.recv() {
-            for sample in data {
-                writer.write_sample(sample).ok();
+        let frames = fifos[0].frames().saturating_sub(target);
+        if frames == 0 {
+            continue;
+        }
+        for part in &mut parts {
+            part.clear();
+        }
+        fifos[0].pull_exact(frames, &mut parts[0]);
+        for (fifo, part) in fifos.iter_mut().zip(&mut parts).skip(1) {
+            let missing = fifo.pull_resampled(frames, part);
+            if missing > 0 {
+                status.overrun(Duration::from_secs_f64(missing as f64 / sample_rate as f64));
             }
         }
 
-        writer.finalize().unwrap();
-        Ok(())
-    });
+        merged.clear();
+        for frame in 0..frames {
+            for (fifo, part) in fifos.iter().zip(&parts) {
+                let channels = fifo.channels();
+                merged.extend_from_slice(&part[frame * channels..(frame + 1) * channels]);
+            }
+        }
+        let selected = merged
+            .chunks_exact(total_channels)
+            .flat_map(|frame| channel_map.iter().map(move |&channel| frame[channel]));
+        if !queue.push(frames * channel_map.len(), selected.clone()) {
+            status.queue_full(Duration::from_secs_f64(frames as f64 / sample_rate as f64));
+        }
+        levels.push(selected);
 
-    record_tui(ui_rx, is_recording.clone())?;
-    is_recording.store(false, Ordering::SeqCst);
-    recording_thread.join().unwrap()?;
-    writer_thread.join().unwrap()?;
+        for ((_, ppm), fifo) in drift.lock().iter_mut().zip(fifos.iter().skip(1)) {
+            *ppm = fifo.drift_ppm();
+        }
+    }
 
+    for stream in &streams {
+        stream.pause()?;
+    }
     Ok(())
 }
 
+/// Opens an input stream that sends each buffer as floats, along with the
+/// time it was captured.
+pub fn build_float_input(
+    device: &cpal::Device,
+    config: SupportedStreamConfig,
+    tx: Sender<(StreamInstant, Vec<f32>)>,
+    err_fn: impl FnMut(StreamError) + Send + 'static,
+) -> anyhow::Result<cpal::Stream> {
+    float_input_stream(
+        device,
+        config,
+        move |data, info| {
+            tx.send((info.timestamp().capture, data.to_vec())).ok();
+        },
+        err_fn,
+    )
+}
+
+/// The native sample layout of the input device. 8-bit devices are recorded as
+/// 16-bit, since 8-bit wav is unsigned and the samples are widened anyway.
 fn wav_spec_from_config(config: &SupportedStreamConfig) -> WavSpec {
+    let (bits_per_sample, sample_format) = match config.sample_format() {
+        SampleFormat::F32 => (32, hound::SampleFormat::Float),
+        SampleFormat::I8 => (16, hound::SampleFormat::Int),
+        format => ((format.sample_size() * 8) as u16, hound::SampleFormat::Int),
+    };
     WavSpec {
         channels: config.channels() as _,
         sample_rate: config.sample_rate().0 as _,
-        bits_per_sample: (config.sample_format().sample_size() * 8) as _,
-        sample_format: if config.sample_format() == SampleFormat::F32 {
-            hound::SampleFormat::Float
-        } else {
-            hound::SampleFormat::Int
-        },
+        bits_per_sample,
+        sample_format,
     }
 }
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Where a channel sits, following the default wav channel order.
+#[derive(Clone, Copy, PartialEq)]
+enum Position {
+    FrontLeft,
+    FrontRight,
+    Centre,
+    Lfe,
+    SurroundLeft,
+    SurroundRight,
+    BackCentre,
+}
+
+/// The layout of a file with this many channels, as wav orders them by
+/// default. Channels of unknown layouts alternate between left and right.
+fn layout(channels: usize) -> Vec<Position> {
+    use Position::*;
+    match channels {
+        1 => vec![Centre],
+        3 => vec![FrontLeft, FrontRight, Centre],
+        4 => vec![FrontLeft, FrontRight, SurroundLeft, SurroundRight],
+        5 => vec![FrontLeft, FrontRight, Centre, SurroundLeft, SurroundRight],
+        6 => vec![
+            FrontLeft,
+            FrontRight,
+            Centre,
+            Lfe,
+            SurroundLeft,
+            SurroundRight,
+        ],
+        7 => vec![
+            FrontLeft,
+            FrontRight,
+            Centre,
+            Lfe,
+            BackCentre,
+            SurroundLeft,
+            SurroundRight,
+        ],
+        8 => vec![
+            FrontLeft,
+            FrontRight,
+            Centre,
+            Lfe,
+            SurroundLeft,
+            SurroundRight,
+            SurroundLeft,
+            SurroundRight,
+        ],
+        _ => (0..channels)
+            .map(|channel| match channel {
+                0 => FrontLeft,
+                1 => FrontRight,
+                _ if channel % 2 == 0 => SurroundLeft,
+                _ => SurroundRight,
+            })
+            .collect(),
+    }
+}
+
+/// How much of a channel at `position` goes to the left and right of a stereo downmix.
+fn stereo_gains(position: Position) -> (f32, f32) {
+    match position {
+        Position::FrontLeft => (1., 0.),
+        Position::FrontRight => (0., 1.),
+        Position::Centre => (FRAC_1_SQRT_2, FRAC_1_SQRT_2),
+        Position::Lfe => (0., 0.),
+        Position::SurroundLeft => (FRAC_1_SQRT_2, 0.),
+        Position::SurroundRight => (0., FRAC_1_SQRT_2),
+        Position::BackCentre => (0.5, 0.5),
+    }
+}
+
+/// The output positions a channel at `position` plays on, with their gains,
+/// given where `find` says the outputs are.
+fn nearest(position: Position, find: &dyn Fn(Position) -> Option<usize>) -> Vec<(Position, f32)> {
+    use Position::*;
+    if find(position).is_some() {
+        return vec![(position, 1.)];
+    }
+    match position {
+        Centre => vec![(FrontLeft, FRAC_1_SQRT_2), (FrontRight, FRAC_1_SQRT_2)],
+        SurroundLeft => vec![(FrontLeft, FRAC_1_SQRT_2)],
+        SurroundRight => vec![(FrontRight, FRAC_1_SQRT_2)],
+        BackCentre if find(SurroundLeft).is_some() && find(SurroundRight).is_some() => {
+            vec![
+                (SurroundLeft, FRAC_1_SQRT_2),
+                (SurroundRight, FRAC_1_SQRT_2),
+            ]
+        }
+        BackCentre => vec![(FrontLeft, 0.5), (FrontRight, 0.5)],
+        // Every layout of two channels or more has front left and right.
+        FrontLeft | FrontRight | Lfe => Vec::new(),
+    }
+}
+
+/// Mixes the channels of a file onto the channels of the output device.
+pub struct Routing {
+    /// Input channel, output channel and gain of every connection.
+    routes: Vec<(usize, usize, f32)>,
+}
+
+impl Routing {
+    /// The standard mix of `inputs` channels onto `outputs` channels.
+    ///
+    /// Mono plays on the first two outputs. Files with no more channels than
+    /// the output play one to one. Surround files are downmixed to stereo with
+    /// the centre and surrounds at -3dB and without the LFE, and anything down
+    /// to mono goes through stereo, with left and right at -3dB. Otherwise each
+    /// channel plays on the output at its position, at -3dB if it shares it
+    /// with others, or else on the nearest outputs, again without the LFE.
+    pub fn standard(inputs: usize, outputs: usize) -> Self {
+        let mut routes = Vec::new();
+        if inputs == 1 {
+            for output in 0..outputs.min(2) {
+                routes.push((0, output, 1.));
+            }
+        } else if inputs <= outputs {
+            for channel in 0..inputs {
+                routes.push((channel, channel, 1.));
+            }
+        } else if outputs <= 2 {
+            for (input, &position) in layout(inputs).iter().enumerate() {
+                let (left, right) = stereo_gains(position);
+                if outputs == 1 {
+                    routes.push((input, 0, (left + right) * FRAC_1_SQRT_2));
+                } else {
+                    routes.push((input, 0, left));
+                    routes.push((input, 1, right));
+                }
+            }
+        } else {
+            let positions = layout(inputs);
+            let output_positions = layout(outputs);
+            let find = |position| output_positions.iter().position(|&p| p == position);
+            for (input, &position) in positions.iter().enumerate() {
+                let shared = positions.iter().filter(|&&p| p == position).count();
+                let gain = if shared > 1 { FRAC_1_SQRT_2 } else { 1. };
+                for (target, weight) in nearest(position, &find) {
+                    if let Some(output) = find(target) {
+                        routes.push((input, output, gain * weight));
+                    }
+                }
+            }
+        }
+        routes.retain(|&(_, _, gain)| gain != 0.);
+        Routing { routes }
+    }
+
+    /// Only the given connections, as `(input, output)` pairs counting from 1.
+    pub fn from_map(map: &[(u16, u16)], inputs: usize, outputs: usize) -> Result<Self> {
+        let routes = map
+            .iter()
+            .map(|&(input, output)| {
+                if input as usize > inputs {
+                    return Err(anyhow!("--map: the file has no channel {}", input));
+                }
+                if output as usize > outputs {
+                    return Err(anyhow!("--map: the device has no output {}", output));
+                }
+                Ok((input as usize - 1, output as usize - 1, 1.))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Routing { routes })
+    }
+
+    /// Mixes one frame of `input` into `output`.
+    pub fn apply(&self, input: &[f32], output: &mut [f32]) {
+        output.fill(0.);
+        for &(from, to, gain) in &self.routes {
+            output[to] += input[from] * gain;
+        }
+    }
+}
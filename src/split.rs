@@ -0,0 +1,146 @@
+use anyhow::{anyhow, Result};
+use colored::*;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::analysis::Segments;
+use crate::cli::{LabelFormat, SplitArgs};
+use crate::decode::Reader;
+use crate::edit::{check_output, Output};
+use crate::raw::RawSpec;
+
+/// Frames processed at a time.
+const CHUNK: usize = 65536;
+
+/// Cue sheet times count frames of a CD, 75 to the second.
+const CUE_FRAMES: u64 = 75;
+
+/// The frames of every part of `reader` between silences.
+fn find_segments(reader: &mut Reader, args: &SplitArgs) -> Result<Vec<Range<u64>>> {
+    let info = &reader.info;
+    let channels = info.channels as usize;
+    let min_frames = (args.min_silence.as_secs_f64() * info.sample_rate as f64).round() as u64;
+    let mut segments = Segments::new(args.threshold, min_frames);
+    let mut buffer = Vec::new();
+    while reader.read(CHUNK, &mut buffer)? > 0 {
+        for frame in buffer.chunks_exact(channels) {
+            segments.add_frame(frame);
+        }
+    }
+    Ok(segments.finish())
+}
+
+/// `mm:ss:ff` in cue sheet frames.
+fn cue_time(frame: u64, sample_rate: u32) -> String {
+    let cue_frames = frame * CUE_FRAMES / sample_rate as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        cue_frames / CUE_FRAMES / 60,
+        cue_frames / CUE_FRAMES % 60,
+        cue_frames % CUE_FRAMES
+    )
+}
+
+fn print_labels(args: &SplitArgs, names: &[String], segments: &[Range<u64>], sample_rate: u32) {
+    let seconds = |frame: u64| frame as f64 / sample_rate as f64;
+    match args.labels {
+        LabelFormat::Audacity => {
+            for (name, segment) in names.iter().zip(segments) {
+                println!(
+                    "{:.6}\t{:.6}\t{}",
+                    seconds(segment.start),
+                    seconds(segment.end),
+                    name
+                );
+            }
+        }
+        LabelFormat::Cue => {
+            println!("FILE \"{}\" WAVE", args.input);
+            for (index, (name, segment)) in names.iter().zip(segments).enumerate() {
+                println!("  TRACK {:02} AUDIO", index + 1);
+                println!("    TITLE \"{}\"", name);
+                println!("    INDEX 01 {}", cue_time(segment.start, sample_rate));
+            }
+        }
+    }
+}
+
+/// Writes the parts of the input to their own files, in a single pass over it.
+fn write_segments(
+    mut reader: Reader,
+    args: &SplitArgs,
+    names: &[String],
+    segments: &[Range<u64>],
+) -> Result<()> {
+    let input = Path::new(&args.input);
+    let sample_rate = reader.info.sample_rate;
+    let channels = reader.info.channels as usize;
+    let mut buffer = Vec::new();
+    // The input frame the buffer starts at, and how many it holds.
+    let mut position = 0;
+    let mut read = 0;
+    for (name, segment) in names.iter().zip(segments) {
+        let mut output = Output::create(Path::new(name), input, &reader, false, segment.start)?;
+        loop {
+            let end = position + read;
+            let first = segment.start.clamp(position, end);
+            let last = segment.end.clamp(position, end);
+            if first < last {
+                output.write(
+                    &buffer[(first - position) as usize * channels
+                        ..(last - position) as usize * channels],
+                )?;
+            }
+            if segment.end <= end {
+                break;
+            }
+            position = end;
+            read = reader.read(CHUNK, &mut buffer)? as u64;
+            if read == 0 {
+                break;
+            }
+        }
+        output.finish()?;
+        println!(
+            "{} {} ({:.2}s to {:.2}s)",
+            "Wrote".green().bold(),
+            name.cyan(),
+            segment.start as f64 / sample_rate as f64,
+            segment.end as f64 / sample_rate as f64
+        );
+    }
+    Ok(())
+}
+
+/// Splits the input at every long enough silence, or prints where it would.
+pub fn split_silence(args: &SplitArgs) -> Result<()> {
+    if !args.out.contains("{n}") {
+        return Err(anyhow!(
+            "--out needs `{{n}}` for the part number, e.g. `part_{{n}}.wav`"
+        ));
+    }
+    let input = Path::new(&args.input);
+    let raw = RawSpec::from_args(&args.raw);
+    let mut reader = Reader::open(input, raw.as_ref())?;
+    let sample_rate = reader.info.sample_rate;
+    let segments = find_segments(&mut reader, args)?;
+    let names: Vec<String> = (1..=segments.len())
+        .map(|n| args.out.replace("{n}", &n.to_string()))
+        .collect();
+
+    if args.dry_run {
+        print_labels(args, &names, &segments, sample_rate);
+        return Ok(());
+    }
+    if segments.is_empty() {
+        println!(
+            "{}",
+            format!("Nothing above {:.1} dBFS in {}", args.threshold, args.input).yellow()
+        );
+        return Ok(());
+    }
+    for name in &names {
+        check_output(input, Path::new(name))?;
+    }
+    write_segments(Reader::open(input, raw.as_ref())?, args, &names, &segments)
+}
@@ -0,0 +1,154 @@
+use anyhow::Result;
+use colored::*;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::analysis::ChannelStats;
+use crate::cli::StatsArgs;
+use crate::decode::Reader;
+use crate::raw::RawSpec;
+
+/// Frames decoded at a time.
+const CHUNK: usize = 65536;
+
+/// Statistics of one channel of a file.
+#[derive(Debug, Serialize)]
+pub struct ChannelReport {
+    pub channel: u16,
+    pub dc_offset: f64,
+    pub peak_dbfs: f64,
+    pub rms_dbfs: f64,
+    pub crest_factor: f64,
+    pub clipped_samples: u64,
+    /// Sign changes per second.
+    pub zero_crossing_rate: f64,
+    pub bits_used: u32,
+    pub noise_floor_dbfs: f64,
+    pub dynamic_range_db: f64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileStats {
+    pub file: String,
+    pub sample_rate: u32,
+    pub bits_per_sample: u32,
+    pub frames: u64,
+    pub duration_secs: f64,
+    pub channels: Vec<ChannelReport>,
+}
+
+/// Decodes `path` once, gathering the statistics of every channel.
+pub fn analyse(path: &Path, raw: Option<&RawSpec>) -> Result<FileStats> {
+    let mut reader = Reader::open(path, raw)?;
+    let info = &reader.info;
+    let sample_rate = info.sample_rate;
+    let bits_per_sample = info.bits_per_sample;
+    let mut stats = vec![ChannelStats::new(sample_rate); info.channels as usize];
+    let mut buffer = Vec::new();
+    while reader.read(CHUNK, &mut buffer)? > 0 {
+        for frame in buffer.chunks_exact(stats.len()) {
+            for (stats, &sample) in stats.iter_mut().zip(frame) {
+                stats.add(sample);
+            }
+        }
+    }
+
+    let frames = stats[0].samples();
+    Ok(FileStats {
+        file: path.display().to_string(),
+        sample_rate,
+        bits_per_sample,
+        frames,
+        duration_secs: frames as f64 / sample_rate as f64,
+        channels: stats
+            .iter()
+            .enumerate()
+            .map(|(index, stats)| ChannelReport {
+                channel: index as u16 + 1,
+                dc_offset: stats.dc_offset(),
+                peak_dbfs: stats.peak_db(),
+                rms_dbfs: stats.rms_db(),
+                crest_factor: stats.crest_factor(),
+                clipped_samples: stats.clipped(),
+                zero_crossing_rate: stats.zero_crossing_rate(),
+                bits_used: stats.bits_used(),
+                noise_floor_dbfs: stats.noise_floor_db(),
+                dynamic_range_db: stats.dynamic_range_db(),
+            })
+            .collect(),
+    })
+}
+
+fn format_number(value: f64, precision: usize) -> String {
+    if value.is_finite() {
+        format!("{:.*}", precision, value)
+    } else if value > 0. {
+        "inf".to_string()
+    } else {
+        "-inf".to_string()
+    }
+}
+
+impl FileStats {
+    /// Prints the statistics as a table with a column per channel.
+    pub fn print(&self) {
+        println!("{}", self.file.cyan().bold());
+        println!(
+            "  {} Hz, {} channels, {}-bit, {} frames ({:.2}s)",
+            self.sample_rate,
+            self.channels.len(),
+            self.bits_per_sample,
+            self.frames,
+            self.duration_secs
+        );
+        let row = |label: &str, value: &dyn Fn(&ChannelReport) -> String| {
+            let values: String = self
+                .channels
+                .iter()
+                .map(|channel| format!("{:>12}", value(channel)))
+                .collect();
+            println!("  {:<18}{}", label, values);
+        };
+        row("", &|channel| format!("ch{}", channel.channel));
+        row("DC offset", &|channel| format!("{:.6}", channel.dc_offset));
+        row("peak dBFS", &|channel| format_number(channel.peak_dbfs, 1));
+        row("RMS dBFS", &|channel| format_number(channel.rms_dbfs, 1));
+        row("crest factor", &|channel| {
+            format_number(channel.crest_factor, 2)
+        });
+        row("clipped samples", &|channel| {
+            channel.clipped_samples.to_string()
+        });
+        row("zero crossings/s", &|channel| {
+            format!("{:.1}", channel.zero_crossing_rate)
+        });
+        row("bits used", &|channel| channel.bits_used.to_string());
+        row("noise floor dBFS", &|channel| {
+            format_number(channel.noise_floor_dbfs, 1)
+        });
+        row("dynamic range dB", &|channel| {
+            format_number(channel.dynamic_range_db, 1)
+        });
+    }
+}
+
+/// Prints the statistics of every input, as a table or as JSON.
+pub fn print_stats(args: &StatsArgs) -> Result<()> {
+    let raw = RawSpec::from_args(&args.raw);
+    let mut all = Vec::new();
+    for (index, input) in args.inputs.iter().enumerate() {
+        let stats = analyse(Path::new(input), raw.as_ref())?;
+        if args.json {
+            all.push(stats);
+        } else {
+            if index > 0 {
+                println!();
+            }
+            stats.print();
+        }
+    }
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&all)?);
+    }
+    Ok(())
+}
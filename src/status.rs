@@ -0,0 +1,255 @@
+use cpal::{StreamError, StreamInstant};
+use crossbeam::channel::{bounded, Receiver, Sender};
+use parking_lot::Mutex;
+use ratatui::style::{Color, Style};
+use ratatui::widgets::Paragraph;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// How many messages are kept for the TUI.
+const RECENT: usize = 50;
+/// Events waiting for the log thread; more are only counted.
+const EVENTS: usize = 256;
+
+/// Something reported from an audio callback, formatted later off the audio
+/// thread.
+enum Event {
+    Input(StreamError),
+    Output(StreamError),
+    Overrun(Duration),
+    QueueFull(Duration),
+    Underrun(Duration),
+    Message(String),
+}
+
+impl Event {
+    fn message(self) -> String {
+        let ms = |lost: Duration| lost.as_secs_f64() * 1000.;
+        match self {
+            Event::Input(err) => format!("input stream: {}", err),
+            Event::Output(err) => format!("output stream: {}", err),
+            Event::Overrun(lost) => format!("overrun: {:.1} ms of input lost", ms(lost)),
+            Event::QueueFull(lost) => {
+                format!("disk queue full: {:.1} ms of input dropped", ms(lost))
+            }
+            Event::Underrun(lost) => format!("underrun: {:.1} ms gap in output", ms(lost)),
+            Event::Message(message) => message,
+        }
+    }
+}
+
+/// Whether a stream error is an xrun, without allocating as `to_string` would.
+fn is_xrun(err: &StreamError) -> bool {
+    match err {
+        StreamError::BackendSpecific { err } => err.description.contains("xrun"),
+        StreamError::DeviceNotAvailable => false,
+    }
+}
+
+/// Stream errors, overruns and underruns, collected from the audio callbacks.
+///
+/// Nothing may be printed while a TUI owns the terminal, so the callbacks
+/// report here instead. The counts go into the TUI's status line and the
+/// take summary, and each message is appended to the `--log` file if given.
+/// Callbacks only count and queue an event; a log thread formats and writes
+/// them, so the audio thread never blocks on a lock or the disk.
+pub struct Status {
+    stream_errors: AtomicU64,
+    overruns: AtomicU64,
+    underruns: AtomicU64,
+    recent: Arc<Mutex<VecDeque<String>>>,
+    events: Option<Sender<(Instant, Event)>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Status {
+    pub fn new(log: Option<&str>) -> anyhow::Result<Self> {
+        let log = match log {
+            Some(path) => Some(LineWriter::new(
+                File::options().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+        let recent = Arc::new(Mutex::new(VecDeque::with_capacity(RECENT)));
+        let (events, receiver) = bounded(EVENTS);
+        let start = Instant::now();
+        let thread = {
+            let recent = recent.clone();
+            std::thread::spawn(move || write_log(receiver, log, recent, start))
+        };
+        Ok(Status {
+            stream_errors: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+            underruns: AtomicU64::new(0),
+            recent,
+            events: Some(events),
+            thread: Some(thread),
+        })
+    }
+
+    pub fn stream_errors(&self) -> u64 {
+        self.stream_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    /// An error passed to an input stream's error callback. JACK reports its
+    /// xruns this way, so those count as overruns.
+    pub fn input_error(&self, err: StreamError) {
+        if is_xrun(&err) {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.stream_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.send(Event::Input(err));
+    }
+
+    /// An error passed to an output stream's error callback.
+    pub fn output_error(&self, err: StreamError) {
+        if is_xrun(&err) {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.stream_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.send(Event::Output(err));
+    }
+
+    /// Input that was lost because it wasn't read in time.
+    pub fn overrun(&self, lost: Duration) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+        self.send(Event::Overrun(lost));
+    }
+
+    /// Input dropped because the disk queue was full.
+    pub fn queue_full(&self, lost: Duration) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+        self.send(Event::QueueFull(lost));
+    }
+
+    /// Output that wasn't delivered in time, heard as a gap.
+    pub fn underrun(&self, lost: Duration) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+        self.send(Event::Underrun(lost));
+    }
+
+    /// A message from outside the audio callbacks, e.g. a file that failed
+    /// to load.
+    pub fn log(&self, message: String) {
+        self.send(Event::Message(message));
+    }
+
+    /// Queues an event without blocking; if the log thread is that far
+    /// behind, the event is only counted.
+    fn send(&self, event: Event) {
+        if let Some(events) = &self.events {
+            events.try_send((Instant::now(), event)).ok();
+        }
+    }
+
+    /// The most recent message, if any.
+    pub fn last(&self) -> Option<String> {
+        self.recent.lock().back().cloned()
+    }
+
+    /// One line for the TUI, e.g. `errors 0  overruns 1  underruns 0  | [ 12.30s] overrun: ...`
+    pub fn line(&self) -> String {
+        let counts = format!(
+            "errors {}  overruns {}  underruns {}",
+            self.stream_errors(),
+            self.overruns(),
+            self.underruns()
+        );
+        match self.last() {
+            Some(last) => format!("{}  | {}", counts, last),
+            None => counts,
+        }
+    }
+
+    pub fn has_problems(&self) -> bool {
+        self.stream_errors() + self.overruns() + self.underruns() > 0
+    }
+
+    /// The status line, highlighted once anything went wrong.
+    pub fn widget(&self) -> Paragraph<'static> {
+        let color = if self.has_problems() {
+            Color::Yellow
+        } else {
+            Color::DarkGray
+        };
+        Paragraph::new(self.line()).style(Style::default().fg(color))
+    }
+}
+
+impl Drop for Status {
+    /// Lets the log thread write what is queued before the program exits.
+    fn drop(&mut self) {
+        self.events.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+/// Formats events as they arrive, keeps the recent ones for the TUI and
+/// appends them to the log file.
+fn write_log(
+    events: Receiver<(Instant, Event)>,
+    mut log: Option<LineWriter<File>>,
+    recent: Arc<Mutex<VecDeque<String>>>,
+    start: Instant,
+) {
+    for (at, event) in events {
+        let message = format!(
+            "[{:>8.2}s] {}",
+            at.duration_since(start).as_secs_f64(),
+            event.message()
+        );
+        if let Some(log) = &mut log {
+            writeln!(log, "{}", message).ok();
+        }
+        let mut recent = recent.lock();
+        if recent.len() == RECENT {
+            recent.pop_front();
+        }
+        recent.push_back(message);
+    }
+}
+
+/// Spots xruns that the host recovers from silently, as gaps between the
+/// timestamps of consecutive callbacks.
+pub struct GapDetector {
+    sample_rate: f64,
+    expected: Option<StreamInstant>,
+}
+
+impl GapDetector {
+    pub fn new(sample_rate: u32) -> Self {
+        GapDetector {
+            sample_rate: sample_rate as f64,
+            expected: None,
+        }
+    }
+
+    /// Takes the timestamp of a buffer of `frames` frames and returns how much
+    /// audio went missing before it.
+    pub fn check(&mut self, at: StreamInstant, frames: usize) -> Option<Duration> {
+        let length = Duration::from_secs_f64(frames as f64 / self.sample_rate);
+        let gap = self
+            .expected
+            .and_then(|expected| at.duration_since(&expected));
+        self.expected = at.add(length);
+        // Timestamps jitter, so only a gap of over half a buffer counts.
+        gap.filter(|gap| *gap > length / 2 && *gap > Duration::from_millis(1))
+    }
+}
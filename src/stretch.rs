@@ -0,0 +1,185 @@
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+use crate::transport::FASTEST;
+
+/// Changes tempo without changing pitch, and pitch without changing tempo.
+///
+/// Tempo is changed with WSOLA: windowed segments of the input are overlapped
+/// at a fixed hop while the input is read at a faster or slower pace, each
+/// segment shifted slightly to where it best continues the previous one.
+/// Pitch is then shifted by resampling the stretched audio, stretched by the
+/// inverse ratio beforehand so the tempo is unchanged.
+pub struct Stretcher {
+    channels: usize,
+    window: Vec<f32>,
+    hop: usize,
+    tolerance: usize,
+    pitch: f64,
+    /// Input pulled so far, per channel; the first frame is input frame `start`.
+    input: Vec<VecDeque<f32>>,
+    start: usize,
+    pulled: Vec<f32>,
+    /// Where the next segment would be taken from without adjustment.
+    analysis: f64,
+    /// Where the last segment would have continued.
+    natural: Option<usize>,
+    /// Overlap-added output; the first `hop` frames are finished.
+    output: Vec<Vec<f32>>,
+    ready: usize,
+    /// Linear interpolation between two stretched frames for the pitch shift.
+    previous: Vec<f32>,
+    current: Vec<f32>,
+    fraction: f64,
+}
+
+impl Stretcher {
+    /// `pitch` is in semitones.
+    pub fn new(channels: usize, sample_rate: u32, pitch: f64) -> Self {
+        // 30ms windows, each segment free to move by 8ms.
+        let size = (sample_rate as usize * 3 / 100) & !1;
+        let window = (0..size)
+            .map(|k| 0.5 - 0.5 * (2. * PI * k as f32 / size as f32).cos())
+            .collect();
+        let hop = size / 2;
+        let tolerance = sample_rate as usize / 125;
+        let pitch = 2f64.powf(pitch / 12.);
+        // The most input ever kept is a hop at the fastest tempo beyond the
+        // last segment, plus a window and the tolerance either side. Reserved
+        // up front so the audio callback never reallocates.
+        let fastest = (FASTEST / pitch).ceil() as usize;
+        let capacity = hop * fastest + size + 2 * tolerance;
+        Stretcher {
+            channels,
+            window,
+            hop,
+            tolerance,
+            pitch,
+            // Not `vec!`, whose clones would drop the capacity.
+            input: (0..channels)
+                .map(|_| VecDeque::with_capacity(capacity))
+                .collect(),
+            start: 0,
+            pulled: vec![0.; channels],
+            analysis: 0.,
+            natural: None,
+            output: vec![vec![0.; size]; channels],
+            ready: size / 2,
+            previous: vec![0.; channels],
+            current: vec![0.; channels],
+            fraction: 0.,
+        }
+    }
+
+    /// Whether the pitch is shifted at all.
+    pub fn shifts_pitch(&self) -> bool {
+        self.pitch != 1.
+    }
+
+    /// Forgets everything, for when the input is bypassed for a while.
+    pub fn reset(&mut self) {
+        for channel in &mut self.input {
+            channel.clear();
+        }
+        self.start = 0;
+        self.analysis = 0.;
+        self.natural = None;
+        for channel in &mut self.output {
+            channel.fill(0.);
+        }
+        self.ready = self.hop;
+        self.fraction = 0.;
+    }
+
+    /// Writes the next output frame into `frame`, reading input at `speed`
+    /// times the normal pace. `pull` supplies the next input frame.
+    pub fn next(&mut self, frame: &mut [f32], speed: f64, mut pull: impl FnMut(&mut [f32])) {
+        self.fraction += self.pitch;
+        while self.fraction >= 1. {
+            self.fraction -= 1.;
+            std::mem::swap(&mut self.previous, &mut self.current);
+            let tempo = speed / self.pitch;
+            if self.ready == self.hop {
+                self.add_segment(tempo, &mut pull);
+            }
+            for (channel, current) in self.current.iter_mut().enumerate() {
+                *current = self.output[channel][self.ready];
+            }
+            self.ready += 1;
+        }
+        let fraction = self.fraction as f32;
+        for (channel, sample) in frame.iter_mut().enumerate().take(self.channels) {
+            let previous = self.previous[channel];
+            *sample = previous + (self.current[channel] - previous) * fraction;
+        }
+    }
+
+    /// Overlap-adds the next segment and makes `hop` more frames ready.
+    fn add_segment(&mut self, tempo: f64, pull: &mut impl FnMut(&mut [f32])) {
+        let size = self.window.len();
+        let nominal = self.analysis.round() as usize;
+        let lowest = nominal.saturating_sub(self.tolerance).max(self.start);
+        let highest = nominal + self.tolerance;
+
+        while self.start + self.input[0].len() < highest + size {
+            pull(&mut self.pulled);
+            for (channel, &sample) in self.input.iter_mut().zip(&self.pulled) {
+                channel.push_back(sample);
+            }
+        }
+
+        let chosen = match self.natural {
+            Some(natural) if natural >= self.start => self.best_match(natural, lowest, highest),
+            _ => nominal.max(self.start),
+        };
+
+        // Shift out the frames already played.
+        for channel in &mut self.output {
+            channel.copy_within(self.hop.., 0);
+            channel[size - self.hop..].fill(0.);
+        }
+        for (channel, input) in self.output.iter_mut().zip(&self.input) {
+            let offset = chosen - self.start;
+            for (k, out) in channel.iter_mut().enumerate() {
+                *out += self.window[k] * input[offset + k];
+            }
+        }
+        self.ready = 0;
+
+        self.natural = Some(chosen + self.hop);
+        self.analysis += self.hop as f64 * tempo;
+        let keep_from = (chosen + self.hop)
+            .min((self.analysis.round() as usize).saturating_sub(self.tolerance));
+        while self.start < keep_from {
+            for channel in &mut self.input {
+                channel.pop_front();
+            }
+            self.start += 1;
+        }
+    }
+
+    /// The start between `lowest` and `highest` whose audio is most like what
+    /// follows `natural`. Only a mono mix of every other position and every
+    /// fourth frame is compared, which is plenty to find the waveform's phase.
+    fn best_match(&self, natural: usize, lowest: usize, highest: usize) -> usize {
+        let mono = |position: usize| -> f32 {
+            self.input
+                .iter()
+                .map(|channel| channel[position - self.start])
+                .sum()
+        };
+        let mut best = lowest;
+        let mut best_score = f32::MIN;
+        for candidate in (lowest..=highest).step_by(2) {
+            let score: f32 = (0..self.hop)
+                .step_by(4)
+                .map(|k| mono(natural + k) * mono(candidate + k))
+                .sum();
+            if score > best_score {
+                best_score = score;
+                best = candidate;
+            }
+        }
+        best
+    }
+}
@@ -0,0 +1,165 @@
+use colored::*;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::analysis::ChannelStats;
+use crate::convert::Samples;
+use crate::record::SILENCE_DB;
+use crate::status::Status;
+
+/// Levels of one recorded channel over the whole take.
+#[derive(Debug, Serialize)]
+pub struct ChannelSummary {
+    pub channel: u16,
+    pub peak_dbfs: f64,
+    pub rms_dbfs: f64,
+    pub clipped_samples: u64,
+}
+
+/// What we check after every take.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub files: Vec<PathBuf>,
+    pub format: String,
+    pub sample_rate: u32,
+    pub frames: u64,
+    pub duration_secs: f64,
+    pub channels: Vec<ChannelSummary>,
+    /// Longest stretch in which every channel stayed below -60 dBFS.
+    pub longest_silence_secs: f64,
+    pub stream_errors: u64,
+    /// Input buffers lost because they weren't read in time.
+    pub overruns: u64,
+}
+
+fn format_db(db: f64) -> String {
+    if db.is_finite() {
+        format!("{:.1} dBFS", db)
+    } else {
+        "-inf dBFS".to_string()
+    }
+}
+
+impl Summary {
+    /// Prints the summary, on stderr if the recording went to stdout.
+    pub fn print(&self) {
+        let to_stderr = self.files.iter().any(|file| file == Path::new("-"));
+        let line = |line: String| {
+            if to_stderr {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        };
+        line("Recording finished".green().bold().to_string());
+        for file in &self.files {
+            line(format!("  {}", file.display().to_string().cyan()));
+        }
+        line(format!(
+            "  {} @ {} Hz, {} frames ({:.2}s)",
+            self.format, self.sample_rate, self.frames, self.duration_secs
+        ));
+        for channel in &self.channels {
+            let clipped = if channel.clipped_samples > 0 {
+                format!("{} clipped", channel.clipped_samples).red().bold()
+            } else {
+                "no clipping".normal()
+            };
+            line(format!(
+                "  ch{:<3} peak {:>12}  rms {:>12}  {}",
+                channel.channel,
+                format_db(channel.peak_dbfs),
+                format_db(channel.rms_dbfs),
+                clipped
+            ));
+        }
+        line(format!(
+            "  longest silence: {:.2}s",
+            self.longest_silence_secs
+        ));
+        let problems = format!(
+            "  stream errors: {}, overruns: {}",
+            self.stream_errors, self.overruns
+        );
+        if self.stream_errors + self.overruns > 0 {
+            line(problems.yellow().to_string());
+        } else {
+            line(problems);
+        }
+    }
+
+    pub fn write_json(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// Accumulates the level statistics of a take as it is written.
+pub struct TakeStats {
+    channels: Vec<u16>,
+    stats: Vec<ChannelStats>,
+    frames: u64,
+    silence: u64,
+    longest_silence: u64,
+    buffer: Vec<f32>,
+}
+
+impl TakeStats {
+    pub fn new(channels: &[u16], sample_rate: u32) -> Self {
+        TakeStats {
+            channels: channels.to_vec(),
+            stats: vec![ChannelStats::new(sample_rate); channels.len()],
+            frames: 0,
+            silence: 0,
+            longest_silence: 0,
+            buffer: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, samples: &Samples) {
+        let threshold = 10f32.powf(SILENCE_DB as f32 / 20.);
+        self.buffer.clear();
+        samples.to_float(&mut self.buffer);
+        for frame in self.buffer.chunks_exact(self.channels.len()) {
+            let mut silent = true;
+            for (stats, &sample) in self.stats.iter_mut().zip(frame) {
+                stats.add(sample);
+                silent &= sample.abs() < threshold;
+            }
+            self.silence = if silent { self.silence + 1 } else { 0 };
+            self.longest_silence = self.longest_silence.max(self.silence);
+            self.frames += 1;
+        }
+    }
+
+    pub fn summary(
+        &self,
+        files: Vec<PathBuf>,
+        format: String,
+        sample_rate: u32,
+        status: &Status,
+    ) -> Summary {
+        let channels = self
+            .channels
+            .iter()
+            .zip(&self.stats)
+            .map(|(&channel, stats)| ChannelSummary {
+                channel,
+                peak_dbfs: stats.peak_db(),
+                rms_dbfs: stats.rms_db(),
+                clipped_samples: stats.clipped(),
+            })
+            .collect();
+        Summary {
+            files,
+            format,
+            sample_rate,
+            frames: self.frames,
+            duration_secs: self.frames as f64 / sample_rate as f64,
+            channels,
+            longest_silence_secs: self.longest_silence as f64 / sample_rate as f64,
+            stream_errors: status.stream_errors(),
+            overruns: status.overruns(),
+        }
+    }
+}
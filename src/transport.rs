@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// No loop point set.
+const UNSET: usize = usize::MAX;
+/// Loop without end.
+pub const FOREVER: u64 = u64::MAX;
+/// The slowest and fastest playback speeds.
+pub const SLOWEST: f64 = 0.25;
+pub const FASTEST: f64 = 2.;
+
+/// Playback position, speed and loop state, shared between the output callback and the TUI.
+///
+/// Positions are frames of the audio as played. When an A-B loop is set it
+/// repeats until cleared; otherwise the whole file repeats `loops` more times.
+pub struct Transport {
+    length: usize,
+    position: AtomicUsize,
+    loops: AtomicU64,
+    a: AtomicUsize,
+    b: AtomicUsize,
+    /// An `f64`, as bits.
+    speed: AtomicU64,
+}
+
+impl Transport {
+    pub fn new(length: usize, loops: u64, speed: f64) -> Self {
+        Transport {
+            length,
+            position: AtomicUsize::new(0),
+            loops: AtomicU64::new(loops),
+            a: AtomicUsize::new(UNSET),
+            b: AtomicUsize::new(UNSET),
+            speed: AtomicU64::new(speed.to_bits()),
+        }
+    }
+
+    pub fn position(&self) -> usize {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position() >= self.length
+    }
+
+    /// Frames left before the end of the file.
+    pub fn remaining(&self) -> usize {
+        self.length.saturating_sub(self.position())
+    }
+
+    pub fn seek(&self, frame: usize) {
+        self.position
+            .store(frame.min(self.length), Ordering::Relaxed);
+    }
+
+    /// Returns the frame to play next and moves on, wrapping around the loop.
+    pub fn advance(&self) -> usize {
+        let position = self.position();
+        if position >= self.length {
+            return position;
+        }
+        let mut next = position + 1;
+        if let Some((a, b)) = self.ab() {
+            if next >= b {
+                next = a;
+            }
+        } else if next >= self.length {
+            let loops = self.loops.load(Ordering::Relaxed);
+            if loops > 0 {
+                if loops != FOREVER {
+                    self.loops.store(loops - 1, Ordering::Relaxed);
+                }
+                next = 0;
+            }
+        }
+        self.position.store(next, Ordering::Relaxed);
+        position
+    }
+
+    pub fn speed(&self) -> f64 {
+        f64::from_bits(self.speed.load(Ordering::Relaxed))
+    }
+
+    /// Changes the speed by `step`, e.g. `0.05` for 5% faster.
+    pub fn change_speed(&self, step: f64) {
+        // Rounded, so stepping back and forth returns to exactly 1.
+        self.set_speed(((self.speed() + step) * 100.).round() / 100.);
+    }
+
+    pub fn set_speed(&self, speed: f64) {
+        self.speed
+            .store(speed.clamp(SLOWEST, FASTEST).to_bits(), Ordering::Relaxed);
+    }
+
+    /// How many more times the whole file repeats.
+    pub fn loops(&self) -> u64 {
+        self.loops.load(Ordering::Relaxed)
+    }
+
+    /// Switches between looping the whole file forever and playing it to the end.
+    pub fn toggle_loop(&self) {
+        let loops = if self.loops() == FOREVER { 0 } else { FOREVER };
+        self.loops.store(loops, Ordering::Relaxed);
+    }
+
+    /// Sets the A loop point at the current position.
+    pub fn set_a(&self) {
+        self.a.store(self.position(), Ordering::Relaxed);
+    }
+
+    /// Sets the B loop point at the current position.
+    pub fn set_b(&self) {
+        self.b.store(self.position(), Ordering::Relaxed);
+    }
+
+    pub fn clear_ab(&self) {
+        self.a.store(UNSET, Ordering::Relaxed);
+        self.b.store(UNSET, Ordering::Relaxed);
+    }
+
+    /// The loop points as set, either of them possibly missing.
+    pub fn points(&self) -> (Option<usize>, Option<usize>) {
+        let point =
+            |point: &AtomicUsize| Some(point.load(Ordering::Relaxed)).filter(|&p| p != UNSET);
+        (point(&self.a), point(&self.b))
+    }
+
+    /// The A-B loop, once both points are set and apart, in order.
+    pub fn ab(&self) -> Option<(usize, usize)> {
+        match self.points() {
+            (Some(a), Some(b)) if a != b => Some((a.min(b), a.max(b))),
+            _ => None,
+        }
+    }
+}
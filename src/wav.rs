@@ -0,0 +1,527 @@
+use hound::{SampleFormat, WavSpec};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::cli::Encoding;
+
+/// Size of the `JUNK` chunk body reserved in front of `fmt `. It is exactly
+/// the size of a `ds64` chunk body, so the header can be promoted to RF64 in
+/// place once the data no longer fits in a 32-bit RIFF size field.
+const DS64_LEN: u32 = 28;
+
+const KSDATAFORMAT_SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+const KSDATAFORMAT_SUBTYPE_IEEE_FLOAT: [u8; 16] = [
+    0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// A streaming WAV writer for recordings of unbounded length.
+///
+/// The file starts out as a plain RIFF WAVE file that any tool can read. If the
+/// data chunk grows beyond what a 32-bit RIFF header can describe, the header
+/// is rewritten as RF64 (EBU Tech 3306) when the file is finalized.
+pub struct WavFileWriter {
+    writer: BufWriter<File>,
+    spec: WavSpec,
+    header_len: u64,
+    data_bytes: u64,
+    padded: bool,
+    /// Chunks to write after the data, and how much of them is on disk.
+    trailer: Vec<u8>,
+    trailer_len: u64,
+}
+
+impl WavFileWriter {
+    pub fn create(path: &Path, spec: WavSpec) -> anyhow::Result<Self> {
+        if spec.sample_format == SampleFormat::Float && spec.bits_per_sample != 32 {
+            return Err(anyhow::anyhow!(
+                "{}-bit float wav is not supported",
+                spec.bits_per_sample
+            ));
+        }
+        if !matches!(spec.bits_per_sample, 8 | 16 | 24 | 32) {
+            return Err(anyhow::anyhow!(
+                "{}-bit wav is not supported",
+                spec.bits_per_sample
+            ));
+        }
+
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = header_bytes(&spec);
+        writer.write_all(&header)?;
+
+        Ok(WavFileWriter {
+            writer,
+            spec,
+            header_len: header.len() as u64,
+            data_bytes: 0,
+            padded: false,
+            trailer: Vec::new(),
+            trailer_len: 0,
+        })
+    }
+
+    /// Number of bytes the file currently occupies on disk.
+    pub fn len(&self) -> u64 {
+        self.header_len + self.data_bytes + self.padded as u64 + self.trailer_len
+    }
+
+    /// Adds a chunk, such as `LIST` or `bext`, to be written after the data
+    /// when the file is finalized.
+    pub fn add_chunk(&mut self, id: [u8; 4], body: &[u8]) {
+        self.trailer.extend_from_slice(&id);
+        self.trailer
+            .extend_from_slice(&(body.len() as u32).to_le_bytes());
+        self.trailer.extend_from_slice(body);
+        if body.len() % 2 == 1 {
+            self.trailer.push(0);
+        }
+    }
+
+    pub fn block_align(&self) -> u64 {
+        self.spec.channels as u64 * (self.spec.bits_per_sample / 8) as u64
+    }
+
+    /// Writes interleaved integer samples, already at the file's bit depth.
+    pub fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        self.data_bytes += write_int(&mut self.writer, samples, self.spec.bits_per_sample)?;
+        Ok(())
+    }
+
+    pub fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.data_bytes += write_float(&mut self.writer, samples)?;
+        Ok(())
+    }
+
+    /// Rewrites the size fields for the data written so far and flushes to
+    /// disk, leaving the writer positioned at the end of the data.
+    ///
+    /// Called periodically while recording, so a take that is interrupted
+    /// before [`finalize`](Self::finalize) is still a readable file.
+    pub fn update_header(&mut self) -> anyhow::Result<()> {
+        let block_align = self.block_align();
+        let riff_len = self.len() - 8;
+        write_sizes(
+            &mut self.writer,
+            riff_len,
+            self.header_len - 4,
+            self.data_bytes,
+            self.data_bytes / block_align,
+        )?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn finalize(mut self) -> anyhow::Result<()> {
+        // Chunks must be word aligned; the pad byte is not part of the data size.
+        if self.data_bytes % 2 == 1 {
+            self.writer.write_all(&[0])?;
+            self.padded = true;
+        }
+        self.writer.write_all(&self.trailer)?;
+        self.trailer_len = self.trailer.len() as u64;
+        self.update_header()?;
+        self.writer.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+/// Writes samples as wav stores them, returning the number of bytes written.
+fn write_int(writer: &mut impl Write, samples: &[i32], bits_per_sample: u16) -> io::Result<u64> {
+    let bytes_per_sample = (bits_per_sample / 8) as usize;
+    for &sample in samples {
+        if bytes_per_sample == 1 {
+            // 8-bit wav is unsigned with an offset of 128.
+            writer.write_all(&[(sample as u8) ^ 0x80])?;
+        } else {
+            writer.write_all(&sample.to_le_bytes()[..bytes_per_sample])?;
+        }
+    }
+    Ok((samples.len() * bytes_per_sample) as u64)
+}
+
+fn write_float(writer: &mut impl Write, samples: &[f32]) -> io::Result<u64> {
+    for &sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    Ok(samples.len() as u64 * 4)
+}
+
+/// Writes samples to a stream that can't seek back, such as a pipe: as wav,
+/// or as headerless PCM in `encoding`.
+///
+/// The wav header goes out first with the RIFF and data sizes set to
+/// `0xFFFFFFFF`, as the length isn't known yet; readers that stream take that
+/// to mean "until the end".
+pub struct PcmWriter {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    spec: WavSpec,
+    encoding: Option<Encoding>,
+    buffer: Vec<u8>,
+    bytes: u64,
+}
+
+impl PcmWriter {
+    pub fn new(
+        writer: Box<dyn Write + Send>,
+        spec: WavSpec,
+        encoding: Option<Encoding>,
+    ) -> anyhow::Result<Self> {
+        let mut writer = BufWriter::new(writer);
+        let mut bytes = 0;
+        if encoding.is_none() {
+            let mut header = header_bytes(&spec);
+            header[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+            let data_len = header.len() - 4;
+            header[data_len..].copy_from_slice(&u32::MAX.to_le_bytes());
+            writer.write_all(&header)?;
+            bytes = header.len() as u64;
+        }
+        Ok(PcmWriter {
+            writer,
+            spec,
+            encoding,
+            buffer: Vec::new(),
+            bytes,
+        })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.bytes
+    }
+
+    pub fn block_align(&self) -> u64 {
+        let width = match self.encoding {
+            Some(encoding) => encoding.width() as u64,
+            None => (self.spec.bits_per_sample / 8) as u64,
+        };
+        self.spec.channels as u64 * width
+    }
+
+    pub fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        self.bytes += match self.encoding {
+            Some(encoding) => {
+                self.buffer.clear();
+                encoding.encode_int(samples, &mut self.buffer);
+                self.writer.write_all(&self.buffer)?;
+                self.buffer.len() as u64
+            }
+            None => write_int(&mut self.writer, samples, self.spec.bits_per_sample)?,
+        };
+        Ok(())
+    }
+
+    pub fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        self.bytes += match self.encoding {
+            Some(encoding) => {
+                self.buffer.clear();
+                encoding.encode_float(samples, &mut self.buffer);
+                self.writer.write_all(&self.buffer)?;
+                self.buffer.len() as u64
+            }
+            None => write_float(&mut self.writer, samples)?,
+        };
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The chunks of a wav file that describe it rather than hold its samples,
+/// such as `LIST` info and `bext`, as `(id, body)` pairs to carry over to an
+/// edited copy. Chunks the writer makes itself and those that point at sample
+/// positions, like cue points and loops, are left out.
+pub fn metadata_chunks<R: Read + Seek>(reader: &mut R) -> Vec<([u8; 4], Vec<u8>)> {
+    let mut chunks = Vec::new();
+    let mut header = [0; 12];
+    if reader.read_exact(&mut header).is_err() || &header[0..4] != b"RIFF" {
+        return chunks;
+    }
+    let mut chunk = [0; 8];
+    while reader.read_exact(&mut chunk).is_ok() {
+        let id: [u8; 4] = chunk[0..4].try_into().unwrap();
+        let len = u32::from_le_bytes(chunk[4..8].try_into().unwrap());
+        // A data chunk of unknown length runs to the end of the file.
+        if len == u32::MAX {
+            break;
+        }
+        let padded = len as i64 + (len & 1) as i64;
+        match &id {
+            b"fmt " | b"data" | b"fact" | b"JUNK" | b"junk" | b"PAD " | b"ds64" | b"cue "
+            | b"smpl" => {
+                if reader.seek(SeekFrom::Current(padded)).is_err() {
+                    break;
+                }
+            }
+            _ => {
+                // Read through `take` so a damaged length can't allocate
+                // more than the file holds.
+                let mut body = Vec::new();
+                let read = reader.by_ref().take(len as u64).read_to_end(&mut body);
+                if read.ok() != Some(len as usize) {
+                    break;
+                }
+                if len & 1 == 1 && reader.seek(SeekFrom::Current(1)).is_err() {
+                    break;
+                }
+                // Labels of cue points.
+                if !(&id == b"LIST" && body.starts_with(b"adtl")) {
+                    chunks.push((id, body));
+                }
+            }
+        }
+    }
+    chunks
+}
+
+/// Sets the data chunk size of wav `bytes` to the data actually there, when
+/// the stream was written before its length was known, or was cut short.
+pub fn fit_streamed_sizes(bytes: &mut [u8]) {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return;
+    }
+    let mut block_align = 1;
+    let mut pos = 12;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_le_bytes(bytes[pos + 4..pos + 8].try_into().unwrap()) as usize;
+        match &bytes[pos..pos + 4] {
+            b"fmt " if pos + 22 <= bytes.len() => {
+                block_align =
+                    u16::from_le_bytes([bytes[pos + 20], bytes[pos + 21]]).max(1) as usize;
+            }
+            b"data" => {
+                let available = bytes.len() - pos - 8;
+                if len == 0 || len > available {
+                    let fitted = (available / block_align * block_align) as u32;
+                    bytes[pos + 4..pos + 8].copy_from_slice(&fitted.to_le_bytes());
+                }
+                return;
+            }
+            _ => {}
+        }
+        pos += 8 + len + (len & 1);
+    }
+}
+
+/// Writes the RIFF and data chunk sizes, promoting the header to RF64 when
+/// they do not fit in 32 bits. The `JUNK` chunk at offset 12 becomes `ds64`.
+fn write_sizes<W: Write + Seek>(
+    writer: &mut W,
+    riff_len: u64,
+    data_len_offset: u64,
+    data_bytes: u64,
+    frames: u64,
+) -> std::io::Result<()> {
+    if riff_len > u32::MAX as u64 {
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(b"RF64")?;
+        writer.write_all(&u32::MAX.to_le_bytes())?;
+        writer.seek(SeekFrom::Start(12))?;
+        writer.write_all(b"ds64")?;
+        writer.write_all(&DS64_LEN.to_le_bytes())?;
+        writer.write_all(&riff_len.to_le_bytes())?;
+        writer.write_all(&data_bytes.to_le_bytes())?;
+        writer.write_all(&frames.to_le_bytes())?;
+        writer.write_all(&0u32.to_le_bytes())?;
+        writer.seek(SeekFrom::Start(data_len_offset))?;
+        writer.write_all(&u32::MAX.to_le_bytes())?;
+    } else {
+        writer.seek(SeekFrom::Start(4))?;
+        writer.write_all(&(riff_len as u32).to_le_bytes())?;
+        writer.seek(SeekFrom::Start(data_len_offset))?;
+        writer.write_all(&(data_bytes as u32).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// What [`repair`] found in a damaged file.
+pub struct Repaired {
+    pub spec: WavSpec,
+    pub frames: u64,
+    /// Bytes of a trailing partial frame that were cut off.
+    pub truncated: u64,
+}
+
+/// Reconstructs the RIFF and data chunk sizes of a wav file from its length on
+/// disk, e.g. after the recording process was killed before finalizing it.
+///
+/// A data chunk whose declared size fits in the file, followed by nothing but
+/// well-formed chunks, is kept as it is, as are the chunks after it. Otherwise
+/// everything after the start of the data chunk is taken to be sample data,
+/// and a trailing partial frame is cut off.
+pub fn repair(path: &Path) -> anyhow::Result<Repaired> {
+    let mut file = File::options().read(true).write(true).open(path)?;
+    let file_len = file.metadata()?.len();
+
+    let mut riff = [0u8; 12];
+    file.read_exact(&mut riff)
+        .map_err(|_| anyhow::anyhow!("file is too short to be a wav file"))?;
+    if !matches!(&riff[0..4], b"RIFF" | b"RF64") || &riff[8..12] != b"WAVE" {
+        return Err(anyhow::anyhow!("not a RIFF WAVE file"));
+    }
+
+    let mut spec = None;
+    let mut has_ds64_room = false;
+    let mut ds64_data_len = None;
+    let mut pos = 12;
+    let (data_offset, declared) = loop {
+        let mut chunk = [0u8; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        if file.read_exact(&mut chunk).is_err() {
+            return Err(anyhow::anyhow!("no data chunk found"));
+        }
+        let id = &chunk[0..4];
+        let len = u32::from_le_bytes(chunk[4..8].try_into()?) as u64;
+
+        match id {
+            b"data" => break (pos + 8, len),
+            b"JUNK" | b"ds64" if pos == 12 && len == DS64_LEN as u64 => {
+                has_ds64_room = true;
+                if id == b"ds64" {
+                    let mut sizes = [0u8; 16];
+                    file.read_exact(&mut sizes)?;
+                    ds64_data_len = Some(u64::from_le_bytes(sizes[8..16].try_into()?));
+                }
+            }
+            b"fmt " => {
+                let mut fmt = [0u8; 16];
+                file.read_exact(&mut fmt)?;
+                let format_tag = u16::from_le_bytes([fmt[0], fmt[1]]);
+                let mut sample_format = match format_tag {
+                    3 => hound::SampleFormat::Float,
+                    _ => hound::SampleFormat::Int,
+                };
+                if format_tag == 0xfffe {
+                    let mut ext = [0u8; 24];
+                    file.read_exact(&mut ext)?;
+                    if ext[8] == 3 {
+                        sample_format = hound::SampleFormat::Float;
+                    }
+                }
+                spec = Some(WavSpec {
+                    channels: u16::from_le_bytes([fmt[2], fmt[3]]),
+                    sample_rate: u32::from_le_bytes(fmt[4..8].try_into()?),
+                    bits_per_sample: u16::from_le_bytes([fmt[14], fmt[15]]),
+                    sample_format,
+                });
+            }
+            _ => {}
+        }
+        pos += 8 + len + (len & 1);
+    };
+
+    let spec = spec.ok_or_else(|| anyhow::anyhow!("no fmt chunk in front of the data"))?;
+    let block_align = spec.channels as u64 * spec.bits_per_sample.div_ceil(8) as u64;
+    if block_align == 0 {
+        return Err(anyhow::anyhow!("fmt chunk describes empty frames"));
+    }
+
+    let declared = match declared {
+        // RF64 keeps the real size in `ds64`.
+        len if len == u32::MAX as u64 => ds64_data_len.unwrap_or(0),
+        len => len,
+    };
+    let (data_bytes, truncated, riff_len) = if declared > 0
+        && data_offset + declared <= file_len
+        && chunks_end_at(&mut file, data_offset + declared + (declared & 1), file_len)?
+    {
+        (declared, 0, file_len - 8)
+    } else {
+        let available = file_len.saturating_sub(data_offset);
+        let data_bytes = available / block_align * block_align;
+        file.set_len(data_offset + data_bytes)?;
+        let pad = data_bytes % 2;
+        if pad == 1 {
+            file.seek(SeekFrom::End(0))?;
+            file.write_all(&[0])?;
+        }
+        (
+            data_bytes,
+            available - data_bytes,
+            data_offset + data_bytes + pad - 8,
+        )
+    };
+
+    if riff_len > u32::MAX as u64 && !has_ds64_room {
+        return Err(anyhow::anyhow!(
+            "data exceeds 4 GiB and there is no room for an RF64 header"
+        ));
+    }
+    write_sizes(
+        &mut file,
+        riff_len,
+        data_offset - 4,
+        data_bytes,
+        data_bytes / block_align,
+    )?;
+    file.sync_all()?;
+
+    Ok(Repaired {
+        spec,
+        frames: data_bytes / block_align,
+        truncated,
+    })
+}
+
+/// Whether the bytes from `pos` to `file_len` are whole chunks, as they are
+/// after the data of a finished file but not after an interrupted recording's
+/// last header update.
+fn chunks_end_at(file: &mut File, mut pos: u64, file_len: u64) -> io::Result<bool> {
+    while pos + 8 <= file_len {
+        let mut chunk = [0u8; 8];
+        file.seek(SeekFrom::Start(pos))?;
+        file.read_exact(&mut chunk)?;
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        pos += 8 + len + (len & 1);
+    }
+    Ok(pos == file_len)
+}
+
+fn header_bytes(spec: &WavSpec) -> Vec<u8> {
+    let bytes_per_sample = spec.bits_per_sample / 8;
+    let block_align = spec.channels * bytes_per_sample;
+    let extensible = spec.channels > 2 || spec.bits_per_sample > 16;
+
+    let mut header = Vec::with_capacity(104);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header.extend_from_slice(b"WAVE");
+
+    header.extend_from_slice(b"JUNK");
+    header.extend_from_slice(&DS64_LEN.to_le_bytes());
+    header.extend_from_slice(&[0; DS64_LEN as usize]);
+
+    header.extend_from_slice(b"fmt ");
+    header.extend_from_slice(&(if extensible { 40u32 } else { 16 }).to_le_bytes());
+    let format_tag: u16 = match (extensible, spec.sample_format) {
+        (true, _) => 0xfffe,
+        (false, SampleFormat::Int) => 1,
+        (false, SampleFormat::Float) => 3,
+    };
+    header.extend_from_slice(&format_tag.to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&(spec.sample_rate * block_align as u32).to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    if extensible {
+        header.extend_from_slice(&22u16.to_le_bytes());
+        header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+        let channel_mask = (0..spec.channels.min(18) as u32).fold(0u32, |m, c| m | 1 << c);
+        header.extend_from_slice(&channel_mask.to_le_bytes());
+        header.extend_from_slice(match spec.sample_format {
+            SampleFormat::Int => &KSDATAFORMAT_SUBTYPE_PCM,
+            SampleFormat::Float => &KSDATAFORMAT_SUBTYPE_IEEE_FLOAT,
+        });
+    }
+
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&0u32.to_le_bytes());
+    header
+}
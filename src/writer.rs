@@ -0,0 +1,437 @@
+use crate::caf::CafWriter;
+use crate::cli::{Encoding, RecFormat};
+use crate::convert::{Quantizer, Samples};
+use crate::flac::FlacWriter;
+use crate::wav::{PcmWriter, WavFileWriter};
+use clap::ValueEnum;
+use hound::{SampleFormat, WavSpec};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// A file format that recordings can be streamed into.
+pub trait Encoder: Send {
+    /// Writes interleaved integer samples, already at the file's bit depth.
+    fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()>;
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()>;
+    /// Makes everything written so far readable, should the process die.
+    fn update_header(&mut self) -> anyhow::Result<()>;
+    /// Size of the file, with an upper bound for samples not yet encoded.
+    fn len(&self) -> u64;
+    /// Upper bound of the bytes a single frame adds to the file.
+    fn frame_bytes(&self) -> u64;
+    fn finalize(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+impl Encoder for WavFileWriter {
+    fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        WavFileWriter::write_int(self, samples)
+    }
+
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        WavFileWriter::write_float(self, samples)
+    }
+
+    fn update_header(&mut self) -> anyhow::Result<()> {
+        WavFileWriter::update_header(self)
+    }
+
+    fn len(&self) -> u64 {
+        WavFileWriter::len(self)
+    }
+
+    fn frame_bytes(&self) -> u64 {
+        WavFileWriter::block_align(self)
+    }
+
+    fn finalize(self: Box<Self>) -> anyhow::Result<()> {
+        WavFileWriter::finalize(*self)
+    }
+}
+
+impl Encoder for CafWriter {
+    fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        CafWriter::write_int(self, samples)
+    }
+
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        CafWriter::write_float(self, samples)
+    }
+
+    fn update_header(&mut self) -> anyhow::Result<()> {
+        CafWriter::update_header(self)
+    }
+
+    fn len(&self) -> u64 {
+        CafWriter::len(self)
+    }
+
+    fn frame_bytes(&self) -> u64 {
+        CafWriter::block_align(self)
+    }
+
+    fn finalize(self: Box<Self>) -> anyhow::Result<()> {
+        CafWriter::finalize(*self)
+    }
+}
+
+impl Encoder for FlacWriter {
+    fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        FlacWriter::write_samples(self, samples)
+    }
+
+    fn write_float(&mut self, _: &[f32]) -> anyhow::Result<()> {
+        Err(anyhow::anyhow!("flac can only store integer samples"))
+    }
+
+    fn update_header(&mut self) -> anyhow::Result<()> {
+        FlacWriter::update_header(self)
+    }
+
+    fn len(&self) -> u64 {
+        FlacWriter::len(self)
+    }
+
+    fn frame_bytes(&self) -> u64 {
+        FlacWriter::frame_bytes(self)
+    }
+
+    fn finalize(self: Box<Self>) -> anyhow::Result<()> {
+        FlacWriter::finalize(*self)
+    }
+}
+
+impl Encoder for PcmWriter {
+    fn write_int(&mut self, samples: &[i32]) -> anyhow::Result<()> {
+        PcmWriter::write_int(self, samples)
+    }
+
+    fn write_float(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+        PcmWriter::write_float(self, samples)
+    }
+
+    /// The header can't be rewritten, but whatever is buffered goes out.
+    fn update_header(&mut self) -> anyhow::Result<()> {
+        PcmWriter::flush(self)
+    }
+
+    fn len(&self) -> u64 {
+        PcmWriter::len(self)
+    }
+
+    fn frame_bytes(&self) -> u64 {
+        PcmWriter::block_align(self)
+    }
+
+    fn finalize(mut self: Box<Self>) -> anyhow::Result<()> {
+        PcmWriter::flush(&mut self)
+    }
+}
+
+impl RecFormat {
+    const ALL: [RecFormat; 7] = [
+        RecFormat::Wav16,
+        RecFormat::Wav24,
+        RecFormat::Wav32,
+        RecFormat::Wav32f,
+        RecFormat::Flac,
+        RecFormat::Caf,
+        RecFormat::Raw,
+    ];
+
+    /// The name used on the command line, e.g. `wav24`.
+    pub fn name(self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default()
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            RecFormat::Wav16 | RecFormat::Wav24 | RecFormat::Wav32 | RecFormat::Wav32f => "wav",
+            RecFormat::Flac => "flac",
+            RecFormat::Caf => "caf",
+            RecFormat::Raw => "raw",
+        }
+    }
+
+    /// Guesses the format from a file name, e.g. `take.flac`.
+    pub fn from_path(path: &str) -> Option<RecFormat> {
+        let ext = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "flac" => Some(RecFormat::Flac),
+            "caf" => Some(RecFormat::Caf),
+            "raw" | "pcm" => Some(RecFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// `take`, `take.wav` -> `take.flac`
+    pub fn output_path(self, output: &str) -> PathBuf {
+        let path = Path::new(output);
+        let known = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| {
+                RecFormat::ALL
+                    .iter()
+                    .any(|format| ext.eq_ignore_ascii_case(format.extension()))
+            });
+        let stem = if known {
+            output[..output.len() - path.extension().unwrap().len() - 1].to_string()
+        } else {
+            output.to_string()
+        };
+        PathBuf::from(format!("{}.{}", stem, self.extension()))
+    }
+
+    /// The wav format that stores samples of the given layout unchanged.
+    pub fn native(spec: &WavSpec) -> RecFormat {
+        match (spec.sample_format, spec.bits_per_sample) {
+            (SampleFormat::Float, _) => RecFormat::Wav32f,
+            (SampleFormat::Int, 32) => RecFormat::Wav32,
+            (SampleFormat::Int, 24) => RecFormat::Wav24,
+            (SampleFormat::Int, _) => RecFormat::Wav16,
+        }
+    }
+
+    /// The sample layout stored in files of this format, when recording from
+    /// a device with the `native` layout.
+    pub fn spec(self, native: &WavSpec) -> WavSpec {
+        let (bits_per_sample, sample_format) = match self {
+            RecFormat::Wav16 => (16, SampleFormat::Int),
+            RecFormat::Wav24 => (24, SampleFormat::Int),
+            RecFormat::Wav32 => (32, SampleFormat::Int),
+            RecFormat::Wav32f => (32, SampleFormat::Float),
+            RecFormat::Flac => match (native.sample_format, native.bits_per_sample) {
+                (SampleFormat::Int, 16) => (16, SampleFormat::Int),
+                _ => (24, SampleFormat::Int),
+            },
+            RecFormat::Caf | RecFormat::Raw => (native.bits_per_sample, native.sample_format),
+        };
+        WavSpec {
+            bits_per_sample,
+            sample_format,
+            ..*native
+        }
+    }
+
+    /// Whether the format can be written to a stream that can't seek, like stdout.
+    pub fn streams(self) -> bool {
+        !matches!(self, RecFormat::Flac | RecFormat::Caf)
+    }
+
+    /// Opens an encoder writing to `path`, or to stdout if it is `-`. Raw
+    /// files are written in `encoding`, by default the one matching `spec`.
+    fn create(
+        self,
+        path: &Path,
+        spec: WavSpec,
+        encoding: Option<Encoding>,
+    ) -> anyhow::Result<Box<dyn Encoder>> {
+        let raw =
+            (self == RecFormat::Raw).then(|| encoding.unwrap_or_else(|| Encoding::native(&spec)));
+        if path == Path::new("-") {
+            if !self.streams() {
+                return Err(anyhow::anyhow!(
+                    "{} cannot be written to stdout",
+                    self.name()
+                ));
+            }
+            return Ok(Box::new(PcmWriter::new(
+                Box::new(std::io::stdout()),
+                spec,
+                raw,
+            )?));
+        }
+        Ok(match self {
+            RecFormat::Wav16 | RecFormat::Wav24 | RecFormat::Wav32 | RecFormat::Wav32f => {
+                Box::new(WavFileWriter::create(path, spec)?)
+            }
+            RecFormat::Flac => Box::new(FlacWriter::create(
+                path,
+                spec.channels,
+                spec.sample_rate,
+                spec.bits_per_sample,
+                &[],
+            )?),
+            RecFormat::Caf => Box::new(CafWriter::create(path, spec)?),
+            RecFormat::Raw => Box::new(PcmWriter::new(
+                Box::new(std::fs::File::create(path)?),
+                spec,
+                raw,
+            )?),
+        })
+    }
+}
+
+/// When to close the current file and continue the take in a new one.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SplitPolicy {
+    pub every: Option<Duration>,
+    pub size: Option<u64>,
+}
+
+impl SplitPolicy {
+    fn is_enabled(&self) -> bool {
+        self.every.is_some() || self.size.is_some()
+    }
+}
+
+/// Writes an interleaved sample stream to one file, or to a numbered series of
+/// files (`name_001.wav`, `name_002.wav`, ...) when a [`SplitPolicy`] is set.
+///
+/// Parts are cut on frame boundaries, so concatenating them reproduces the
+/// take sample for sample. The header of the file being written is brought up
+/// to date every `flush_interval`, so a crash loses at most that much audio.
+pub struct RecordingWriter {
+    output: PathBuf,
+    format: RecFormat,
+    spec: WavSpec,
+    policy: SplitPolicy,
+    encoding: Option<Encoding>,
+    current: Box<dyn Encoder>,
+    samples_in_part: u64,
+    paths: Vec<PathBuf>,
+    flush_interval: Duration,
+    last_flush: Instant,
+    quantizer: Quantizer,
+    int_buffer: Vec<i32>,
+    float_buffer: Vec<f32>,
+}
+
+impl RecordingWriter {
+    pub fn create(
+        output: &Path,
+        format: RecFormat,
+        spec: WavSpec,
+        encoding: Option<Encoding>,
+        policy: SplitPolicy,
+        flush_interval: Duration,
+    ) -> anyhow::Result<Self> {
+        let path = if policy.is_enabled() {
+            part_path(output, 1)
+        } else {
+            output.to_path_buf()
+        };
+        let current = format.create(&path, spec, encoding)?;
+
+        Ok(RecordingWriter {
+            output: output.to_path_buf(),
+            format,
+            spec,
+            encoding,
+            policy,
+            current,
+            samples_in_part: 0,
+            paths: vec![path],
+            flush_interval,
+            last_flush: Instant::now(),
+            quantizer: Quantizer::new(spec.bits_per_sample),
+            int_buffer: Vec::new(),
+            float_buffer: Vec::new(),
+        })
+    }
+
+    /// Writes native samples, converting them to the file's sample format.
+    pub fn write(&mut self, samples: &Samples) -> anyhow::Result<()> {
+        match self.spec.sample_format {
+            SampleFormat::Int => {
+                let mut buffer = std::mem::take(&mut self.int_buffer);
+                buffer.clear();
+                self.quantizer.quantize(samples, &mut buffer);
+                let result = self.write_parts(&buffer, |encoder, part| encoder.write_int(part));
+                self.int_buffer = buffer;
+                result?;
+            }
+            SampleFormat::Float => {
+                let mut buffer = std::mem::take(&mut self.float_buffer);
+                buffer.clear();
+                samples.to_float(&mut buffer);
+                let result = self.write_parts(&buffer, |encoder, part| encoder.write_float(part));
+                self.float_buffer = buffer;
+                result?;
+            }
+        }
+
+        if self.last_flush.elapsed() >= self.flush_interval {
+            self.current.update_header()?;
+            self.last_flush = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn write_parts<T>(
+        &mut self,
+        mut samples: &[T],
+        write: impl Fn(&mut dyn Encoder, &[T]) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let channels = self.spec.channels as u64;
+        while !samples.is_empty() {
+            let mut room = samples.len() as u64;
+            if let Some(every) = self.policy.every {
+                let frames = (every.as_secs_f64() * self.spec.sample_rate as f64).round() as u64;
+                room = room.min((frames.max(1) * channels).saturating_sub(self.samples_in_part));
+            }
+            if let Some(size) = self.policy.size {
+                let frames = size.saturating_sub(self.current.len()) / self.current.frame_bytes();
+                room = room.min(frames * channels);
+            }
+            if room == 0 {
+                if self.samples_in_part == 0 {
+                    // The limit is smaller than a single frame, write one
+                    // frame per file rather than not at all.
+                    room = channels.min(samples.len() as u64);
+                } else {
+                    self.roll_over()?;
+                    continue;
+                }
+            }
+
+            let room = room as usize;
+            write(self.current.as_mut(), &samples[..room])?;
+            self.samples_in_part += room as u64;
+            samples = &samples[room..];
+        }
+        Ok(())
+    }
+
+    fn roll_over(&mut self) -> anyhow::Result<()> {
+        let path = part_path(&self.output, self.paths.len() + 1);
+        let next = self.format.create(&path, self.spec, self.encoding)?;
+        std::mem::replace(&mut self.current, next).finalize()?;
+        self.samples_in_part = 0;
+        self.last_flush = Instant::now();
+        self.paths.push(path);
+        Ok(())
+    }
+
+    /// Finalizes the last file and returns the paths of every file written.
+    pub fn finalize(self) -> anyhow::Result<Vec<PathBuf>> {
+        self.current.finalize()?;
+        Ok(self.paths)
+    }
+}
+
+/// `take.wav` -> `take_003.wav`
+fn part_path(output: &Path, index: usize) -> PathBuf {
+    suffixed_path(output, &format!("{:03}", index))
+}
+
+/// `take.wav` -> `take_ch3.wav`
+pub fn channel_path(output: &Path, channel: u16) -> PathBuf {
+    suffixed_path(output, &format!("ch{}", channel))
+}
+
+/// `take.wav` -> `take_dev3.wav`
+pub fn device_path(output: &Path, device: u8) -> PathBuf {
+    suffixed_path(output, &format!("dev{}", device))
+}
+
+fn suffixed_path(output: &Path, suffix: &str) -> PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match output.extension() {
+        Some(ext) => format!("{}_{}.{}", stem, suffix, ext.to_string_lossy()),
+        None => format!("{}_{}", stem, suffix),
+    };
+    output.with_file_name(name)
+}